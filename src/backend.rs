@@ -0,0 +1,164 @@
+use std::{fmt, iter};
+
+use serde::Serialize;
+
+use crate::{error::Result, subscription::Subscription};
+
+#[cfg(target_os = "linux")]
+mod dbus;
+pub mod fake;
+#[cfg(target_os = "linux")]
+pub mod fcitx;
+#[cfg(target_os = "linux")]
+pub mod ibus;
+#[cfg(target_os = "macos")]
+pub mod tis;
+
+/// An input source available on the current system.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct InputSource {
+    /// The unique ID of the input source, e.g. `com.apple.keylayout.ABC`.
+    pub id: String,
+    /// The localized name of the input source, e.g. `ABC`.
+    pub name: String,
+    /// The languages supported by the input source as BCP 47 tags, the
+    /// primary one first.
+    pub languages: Vec<String>,
+    /// The backend-specific category of the input source, e.g.
+    /// `TISCategoryKeyboardInputSource`.
+    pub category: String,
+    /// Whether the input source has been enabled by the user.
+    pub enabled: bool,
+    /// Whether the input source can be selected with
+    /// [`InputSourceBackend::select`].
+    pub selectable: bool,
+}
+
+/// A list of [`InputSource`]s, displayed as a table.
+#[derive(Clone, Copy, Debug)]
+pub struct SourceTable<'a>(pub &'a [InputSource]);
+
+impl fmt::Display for SourceTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| (if b { "yes" } else { "no" }).to_owned();
+        let header = [
+            "ID",
+            "NAME",
+            "LANGUAGES",
+            "CATEGORY",
+            "ENABLED",
+            "SELECTABLE",
+        ];
+        let rows: Vec<[String; 6]> = (self.0.iter())
+            .map(|src| {
+                [
+                    src.id.clone(),
+                    src.name.clone(),
+                    src.languages.join(","),
+                    src.category.clone(),
+                    yes_no(src.enabled),
+                    yes_no(src.selectable),
+                ]
+            })
+            .collect();
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (w, cell) in widths.iter_mut().zip(row) {
+                *w = (*w).max(cell.chars().count());
+            }
+        }
+
+        let header = header.map(ToOwned::to_owned);
+        for (i, row) in iter::once(&header).chain(&rows).enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            let line = (row.iter().zip(widths))
+                .map(|(cell, w)| format!("{cell:w$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            f.write_str(line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// A platform-specific way of querying and switching input sources.
+pub trait InputSourceBackend {
+    /// Returns the ID of the current input source.
+    fn current(&self) -> Result<String>;
+
+    /// Selects the input source identified by `id`.
+    ///
+    /// Returns `false` if no such input source is available.
+    fn select(&self, id: &str) -> Result<bool>;
+
+    /// Returns the input sources that have been enabled by the user, or all
+    /// the installed ones if `include_all` is set.
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>>;
+
+    /// Subscribes to the changes of the current input source, receiving the
+    /// IDs of the newly selected input sources.
+    fn subscribe(&self) -> Result<Subscription<String>>;
+
+    /// Returns if every change of the current input source is received by
+    /// [`Self::subscribe`], including the ones reverted shortly after.
+    ///
+    /// This is not the case for backends polling the current input source.
+    fn reports_every_change(&self) -> bool {
+        true
+    }
+}
+
+impl<B: InputSourceBackend + ?Sized> InputSourceBackend for Box<B> {
+    fn current(&self) -> Result<String> {
+        (**self).current()
+    }
+
+    fn select(&self, id: &str) -> Result<bool> {
+        (**self).select(id)
+    }
+
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>> {
+        (**self).list(include_all)
+    }
+
+    fn subscribe(&self) -> Result<Subscription<String>> {
+        (**self).subscribe()
+    }
+
+    fn reports_every_change(&self) -> bool {
+        (**self).reports_every_change()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_table() {
+        let srcs = [
+            InputSource {
+                id: "com.apple.keylayout.ABC".into(),
+                name: "ABC".into(),
+                languages: vec!["en".into()],
+                category: "TISCategoryKeyboardInputSource".into(),
+                enabled: true,
+                selectable: true,
+            },
+            InputSource {
+                id: "com.apple.PressAndHold".into(),
+                name: "Press and Hold".into(),
+                ..InputSource::default()
+            },
+        ];
+        assert_eq!(
+            SourceTable(&srcs).to_string(),
+            "\
+ID                       NAME            LANGUAGES  CATEGORY                        ENABLED  SELECTABLE
+com.apple.keylayout.ABC  ABC             en         TISCategoryKeyboardInputSource  yes      yes
+com.apple.PressAndHold   Press and Hold                                             no       no"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Helpers shared by the D-Bus based backends.
+
+use smol::{channel, stream::StreamExt};
+use zbus::{blocking::Proxy, message::Message};
+
+use crate::{error::Result, subscription::Subscription};
+
+/// Subscribes to the signal `name` of the remote object behind `proxy`,
+/// turning each signal received into an input source ID with `id`.
+///
+/// Signals for which `id` returns `None` are skipped.
+pub fn subscribe_signal(
+    proxy: &Proxy<'static>,
+    name: &'static str,
+    id: impl Fn(&Message) -> Option<String> + Send + 'static,
+) -> Result<Subscription<String>> {
+    let mut signals = smol::block_on(proxy.inner().receive_signal(name))?;
+    let (tx, rx) = channel::unbounded();
+    let task = smol::spawn(async move {
+        while let Some(msg) = signals.next().await {
+            if let Some(id) = id(&msg)
+                && tx.send(id).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    Ok(Subscription::new(rx, task))
+}
+
+#[cfg(test)]
+pub mod test_bus {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    use tempfile::TempDir;
+    use zbus::blocking::{Connection, connection};
+
+    /// A private session bus, shut down when dropped.
+    #[derive(Debug)]
+    pub struct TestBus {
+        daemon: Child,
+        address: String,
+        _dir: TempDir,
+    }
+
+    impl TestBus {
+        /// Starts a new `dbus-daemon`, which must be available in `$PATH`.
+        pub fn start() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .arg(format!("--address=unix:dir={}", dir.path().display()))
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("failed to start `dbus-daemon`");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_owned(),
+                _dir: dir,
+            }
+        }
+
+        pub fn builder(&self) -> connection::Builder<'_> {
+            connection::Builder::address(self.address.as_str()).unwrap()
+        }
+
+        pub fn connect(&self) -> Connection {
+            self.builder().build().unwrap()
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            _ = self.daemon.kill();
+            _ = self.daemon.wait();
+        }
+    }
+}
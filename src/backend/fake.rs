@@ -0,0 +1,150 @@
+use std::sync::{Arc, Mutex};
+
+use smol::channel::{self, Sender};
+
+use super::{InputSource, InputSourceBackend};
+use crate::{error::Result, subscription::Subscription};
+
+/// An in-memory [`InputSourceBackend`] for testing purposes.
+///
+/// Clones of a `FakeBackend` share the same underlying state, so one of them
+/// can be handed to the code under test while another one is used to
+/// simulate user interactions with [`FakeBackend::switch`].
+#[must_use]
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend(Arc<Mutex<FakeBackendInner>>);
+
+#[derive(Debug, Default)]
+struct FakeBackendInner {
+    sources: Vec<InputSource>,
+    current: String,
+    selections: Vec<String>,
+    subscribers: Vec<Sender<String>>,
+    polled: bool,
+}
+
+impl FakeBackend {
+    /// Creates a new backend with the given input sources available,
+    /// the first of which is selected initially.
+    pub fn new(sources: impl IntoIterator<Item = InputSource>) -> Self {
+        let sources: Vec<_> = sources.into_iter().collect();
+        let current = sources.first().map(|s| s.id.clone()).unwrap_or_default();
+        Self(Arc::new(Mutex::new(FakeBackendInner {
+            sources,
+            current,
+            ..FakeBackendInner::default()
+        })))
+    }
+
+    /// Creates a new backend with the input sources identified by `ids`
+    /// enabled, using the IDs as their names as well.
+    pub fn with_ids<S: AsRef<str>>(ids: impl IntoIterator<Item = S>) -> Self {
+        Self::new(ids.into_iter().map(|id| InputSource {
+            id: id.as_ref().to_owned(),
+            name: id.as_ref().to_owned(),
+            enabled: true,
+            selectable: true,
+            ..InputSource::default()
+        }))
+    }
+
+    /// Makes the backend claim not to report every change of the current
+    /// input source, like the backends polling it.
+    pub fn with_polling(self) -> Self {
+        self.0.lock().unwrap().polled = true;
+        self
+    }
+
+    /// Simulates a manual input source switch by the user.
+    ///
+    /// Returns `false` if no such input source is available.
+    #[allow(clippy::must_use_candidate)]
+    pub fn switch(&self, id: &str) -> bool {
+        let mut inner = self.0.lock().unwrap();
+        if !inner.sources.iter().any(|s| s.id == id && s.selectable) {
+            return false;
+        }
+        if inner.current != id {
+            id.clone_into(&mut inner.current);
+            inner
+                .subscribers
+                .retain(|tx| tx.try_send(id.to_owned()).is_ok());
+        }
+        true
+    }
+
+    /// Returns the IDs of the input sources selected through
+    /// [`InputSourceBackend::select`] so far, in chronological order.
+    #[must_use]
+    pub fn selections(&self) -> Vec<String> {
+        self.0.lock().unwrap().selections.clone()
+    }
+}
+
+impl InputSourceBackend for FakeBackend {
+    fn current(&self) -> Result<String> {
+        Ok(self.0.lock().unwrap().current.clone())
+    }
+
+    fn select(&self, id: &str) -> Result<bool> {
+        if !self.switch(id) {
+            return Ok(false);
+        }
+        self.0.lock().unwrap().selections.push(id.to_owned());
+        Ok(true)
+    }
+
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>> {
+        let inner = self.0.lock().unwrap();
+        Ok((inner.sources.iter())
+            .filter(|s| include_all || s.enabled)
+            .cloned()
+            .collect())
+    }
+
+    fn subscribe(&self) -> Result<Subscription<String>> {
+        let (tx, rx) = channel::unbounded();
+        self.0.lock().unwrap().subscribers.push(tx);
+        Ok(Subscription::new(rx, ()))
+    }
+
+    fn reports_every_change(&self) -> bool {
+        !self.0.lock().unwrap().polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_backend() {
+        let backend = FakeBackend::with_ids(["abc", "pinyin"]);
+        let sub = backend.subscribe().unwrap();
+        let rx = sub.receiver();
+        assert_eq!(backend.current().unwrap(), "abc");
+
+        assert!(backend.select("pinyin").unwrap());
+        assert!(!backend.select("kotoeri").unwrap());
+        assert!(backend.switch("abc"));
+        assert_eq!(backend.current().unwrap(), "abc");
+        assert_eq!(backend.selections(), ["pinyin"]);
+        assert_eq!(backend.list(false).unwrap().len(), 2);
+
+        assert_eq!(rx.try_recv().as_deref(), Ok("pinyin"));
+        assert_eq!(rx.try_recv().as_deref(), Ok("abc"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_list_installed() {
+        let disabled = InputSource {
+            id: "kotoeri".into(),
+            selectable: true,
+            ..InputSource::default()
+        };
+        let backend = FakeBackend::new([disabled]);
+        assert!(backend.list(false).unwrap().is_empty());
+        assert_eq!(backend.list(true).unwrap().len(), 1);
+    }
+}
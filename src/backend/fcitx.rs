@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use smol::{Timer, channel};
+use zbus::blocking::{Connection, Proxy};
+
+use super::{InputSource, InputSourceBackend};
+use crate::{error::Result, subscription::Subscription};
+
+const FCITX_SERVICE: &str = "org.fcitx.Fcitx5";
+const FCITX_PATH: &str = "/controller";
+const FCITX_INTERFACE: &str = "org.fcitx.Fcitx.Controller1";
+
+/// How often to check for input method changes, since the Fcitx5 controller
+/// does not signal them.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// An input method as listed by `AvailableInputMethods`: the unique name, the
+/// name, the native name, the icon, the label, the language code and whether
+/// it is configurable.
+type InputMethodEntry = (String, String, String, String, String, String, bool);
+
+/// The [`InputSourceBackend`] backed by the Fcitx5 daemon.
+///
+/// Input sources are identified by the unique names of Fcitx5 input methods,
+/// e.g. `keyboard-us` or `pinyin`.
+#[derive(Clone, Debug)]
+pub struct FcitxBackend {
+    proxy: Proxy<'static>,
+}
+
+impl FcitxBackend {
+    /// Connects to the Fcitx5 daemon over the session bus.
+    pub fn new() -> Result<Self> {
+        Self::with_connection(&Connection::session()?)
+    }
+
+    /// Talks to the Fcitx5 daemon over `conn`.
+    pub fn with_connection(conn: &Connection) -> Result<Self> {
+        let proxy = Proxy::new(conn, FCITX_SERVICE, FCITX_PATH, FCITX_INTERFACE)?;
+        Ok(Self { proxy })
+    }
+
+    /// Returns the unique names of the input methods in the current group.
+    fn enabled(&self) -> Result<Vec<String>> {
+        let group: String = self.proxy.call("CurrentInputMethodGroup", &())?;
+        let (_layout, items): (String, Vec<(String, String)>) =
+            self.proxy.call("InputMethodGroupInfo", &group)?;
+        Ok(items.into_iter().map(|(im, _layout)| im).collect())
+    }
+}
+
+impl InputSourceBackend for FcitxBackend {
+    fn current(&self) -> Result<String> {
+        Ok(self.proxy.call("CurrentInputMethod", &())?)
+    }
+
+    fn select(&self, id: &str) -> Result<bool> {
+        if self.current()? == id {
+            return Ok(true);
+        }
+        if !self.enabled()?.iter().any(|im| im == id) {
+            return Ok(false);
+        }
+        self.proxy.call_method("SetCurrentIM", &id)?;
+        Ok(true)
+    }
+
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>> {
+        let enabled = self.enabled()?;
+        let ims: Vec<InputMethodEntry> = self.proxy.call("AvailableInputMethods", &())?;
+        Ok(ims
+            .into_iter()
+            .map(
+                |(id, name, _native, _icon, _label, language, _configurable)| {
+                    InputSource {
+                        languages: (!language.is_empty())
+                            .then(|| language.replace('_', "-"))
+                            .into_iter()
+                            .collect(),
+                        category: (if id.starts_with("keyboard-") {
+                            "keyboard"
+                        } else {
+                            "input-method"
+                        })
+                        .to_owned(),
+                        enabled: enabled.contains(&id),
+                        // Only the input methods in the current group can be
+                        // activated.
+                        selectable: enabled.contains(&id),
+                        id,
+                        name,
+                    }
+                },
+            )
+            .filter(|src| include_all || src.enabled)
+            .collect())
+    }
+
+    fn subscribe(&self) -> Result<Subscription<String>> {
+        let proxy = self.proxy.inner().clone();
+        let mut prev = self.current()?;
+        let (tx, rx) = channel::unbounded();
+        let task = smol::spawn(async move {
+            loop {
+                Timer::after(POLL_INTERVAL).await;
+                let Ok(curr) = proxy.call::<_, _, String>("CurrentInputMethod", &()).await else {
+                    continue;
+                };
+                if curr == prev {
+                    continue;
+                }
+                prev.clone_from(&curr);
+                if tx.send(curr).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Subscription::new(rx, task))
+    }
+
+    fn reports_every_change(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smol::future;
+    use zbus::interface;
+
+    use super::*;
+    use crate::backend::dbus::test_bus::TestBus;
+
+    /// A stub of the Fcitx5 controller.
+    struct StubController {
+        group_name: &'static str,
+        available: Vec<(&'static str, &'static str, &'static str)>,
+        group: Vec<&'static str>,
+        current: String,
+    }
+
+    #[interface(name = "org.fcitx.Fcitx.Controller1")]
+    impl StubController {
+        fn current_input_method(&self) -> String {
+            self.current.clone()
+        }
+
+        #[zbus(name = "SetCurrentIM")]
+        fn set_current_im(&mut self, im: String) {
+            self.current = im;
+        }
+
+        fn current_input_method_group(&self) -> String {
+            self.group_name.into()
+        }
+
+        fn input_method_group_info(&self, name: &str) -> (String, Vec<(String, String)>) {
+            let items = (self.group.iter())
+                .filter(|_| name == self.group_name)
+                .map(|im| ((*im).into(), String::new()));
+            ("keyboard-us".into(), items.collect())
+        }
+
+        fn available_input_methods(&self) -> Vec<InputMethodEntry> {
+            (self.available.iter())
+                .map(|&(id, name, lang)| {
+                    let s = ToOwned::to_owned;
+                    (s(id), s(name), s(name), s(""), s(""), s(lang), false)
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    #[ignore = "requires `dbus-daemon`"]
+    fn test_fcitx_backend() {
+        let bus = TestBus::start();
+        let stub = StubController {
+            group_name: "Default",
+            available: vec![
+                ("keyboard-us", "English (US)", "en"),
+                ("pinyin", "Pinyin", "zh_CN"),
+                ("mozc", "Mozc", "ja"),
+            ],
+            group: vec!["keyboard-us", "pinyin"],
+            current: "keyboard-us".into(),
+        };
+        let service = (bus.builder())
+            .name(FCITX_SERVICE)
+            .unwrap()
+            .serve_at(FCITX_PATH, stub)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let backend = FcitxBackend::with_connection(&bus.connect()).unwrap();
+        let im_changes = backend.subscribe().unwrap();
+        assert_eq!(backend.current().unwrap(), "keyboard-us");
+
+        let enabled = backend.list(false).unwrap();
+        assert_eq!(enabled.len(), 2);
+        assert_eq!(enabled[1].name, "Pinyin");
+        assert_eq!(enabled[1].languages, ["zh-CN"]);
+        let all = backend.list(true).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(!all[2].selectable);
+
+        let recv = || {
+            smol::block_on(future::or(
+                async { im_changes.receiver().recv().await.ok() },
+                async {
+                    Timer::after(Duration::from_secs(5)).await;
+                    None
+                },
+            ))
+        };
+
+        assert!(backend.select("pinyin").unwrap());
+        assert!(!backend.select("mozc").unwrap());
+        assert_eq!(backend.current().unwrap(), "pinyin");
+        assert_eq!(recv().as_deref(), Some("pinyin"));
+
+        // Simulate a manual switch by the user.
+        let stub = Proxy::new(&service, FCITX_SERVICE, FCITX_PATH, FCITX_INTERFACE).unwrap();
+        stub.call_method("SetCurrentIM", &"keyboard-us").unwrap();
+        assert_eq!(recv().as_deref(), Some("keyboard-us"));
+    }
+}
@@ -0,0 +1,247 @@
+use zbus::{
+    blocking::{Connection, Proxy, connection, proxy},
+    proxy::CacheProperties,
+    zvariant::{OwnedValue, Value},
+};
+
+use super::{InputSource, InputSourceBackend, dbus::subscribe_signal};
+use crate::{
+    error::{Error, Result},
+    subscription::Subscription,
+};
+
+const IBUS_SERVICE: &str = "org.freedesktop.IBus";
+const IBUS_PATH: &str = "/org/freedesktop/IBus";
+const IBUS_INTERFACE: &str = "org.freedesktop.IBus";
+
+/// The [`InputSourceBackend`] backed by the IBus daemon.
+///
+/// Input sources are identified by IBus engine names, e.g. `xkb:us::eng` or
+/// `libpinyin`.
+#[derive(Clone, Debug)]
+pub struct IBusBackend {
+    proxy: Proxy<'static>,
+}
+
+impl IBusBackend {
+    /// Connects to the IBus daemon of the current session.
+    pub fn new() -> Result<Self> {
+        Self::with_connection(&connection::Builder::ibus()?.build()?)
+    }
+
+    /// Talks to the IBus daemon over `conn`.
+    pub fn with_connection(conn: &Connection) -> Result<Self> {
+        let proxy = proxy::Builder::new(conn)
+            .destination(IBUS_SERVICE)?
+            .path(IBUS_PATH)?
+            .interface(IBUS_INTERFACE)?
+            // IBus does not emit `PropertiesChanged` for the global engine.
+            .cache_properties(CacheProperties::No)
+            .build()?;
+        Ok(Self { proxy })
+    }
+
+    fn engines(&self, method: &str) -> Result<Vec<InputSource>> {
+        let descs: Vec<OwnedValue> = self.proxy.call(method, &())?;
+        descs
+            .iter()
+            .map(|desc| engine_desc(desc).ok_or(Error::MalformedReply("IBus")))
+            .collect()
+    }
+}
+
+impl InputSourceBackend for IBusBackend {
+    fn current(&self) -> Result<String> {
+        let desc: OwnedValue = self.proxy.get_property("GlobalEngine")?;
+        engine_desc(&desc)
+            .map(|src| src.id)
+            .ok_or(Error::MalformedReply("IBus"))
+    }
+
+    fn select(&self, id: &str) -> Result<bool> {
+        if self.current()? == id {
+            return Ok(true);
+        }
+        if !self.list(true)?.iter().any(|src| src.id == id) {
+            return Ok(false);
+        }
+        self.proxy.call_method("SetGlobalEngine", &id)?;
+        Ok(true)
+    }
+
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>> {
+        let mut srcs = self.engines("ListEngines")?;
+        let active = self.engines("ListActiveEngines")?;
+        for src in &mut srcs {
+            src.enabled = active.iter().any(|it| it.id == src.id);
+        }
+        if !include_all {
+            srcs.retain(|src| src.enabled);
+        }
+        Ok(srcs)
+    }
+
+    fn subscribe(&self) -> Result<Subscription<String>> {
+        subscribe_signal(&self.proxy, "GlobalEngineChanged", |msg| {
+            msg.body().deserialize().ok()
+        })
+    }
+}
+
+/// Parses a serialized `IBusEngineDesc`.
+///
+/// An `IBusEngineDesc` is serialized as a structure whose first field is the
+/// type name, followed by a dictionary of attachments, the engine name, the
+/// long name, the description and the language, among others.
+fn engine_desc(desc: &Value<'_>) -> Option<InputSource> {
+    let mut desc = desc;
+    while let Value::Value(inner) = desc {
+        desc = inner;
+    }
+    let Value::Structure(desc) = desc else {
+        return None;
+    };
+    let str_field = |i: usize| match desc.fields().get(i)? {
+        Value::Str(s) => Some(s.as_str()),
+        _ => None,
+    };
+    if str_field(0)? != "IBusEngineDesc" {
+        return None;
+    }
+    let id = str_field(2)?;
+    let language = str_field(5)?;
+    Some(InputSource {
+        id: id.to_owned(),
+        name: str_field(3)?.to_owned(),
+        languages: (!language.is_empty())
+            .then(|| language.replace('_', "-"))
+            .into_iter()
+            .collect(),
+        category: (if id.starts_with("xkb:") {
+            "xkb"
+        } else {
+            "engine"
+        })
+        .to_owned(),
+        enabled: true,
+        selectable: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use smol::{Timer, future};
+    use zbus::{interface, object_server::SignalEmitter, zvariant::StructureBuilder};
+
+    use super::*;
+    use crate::backend::dbus::test_bus::TestBus;
+
+    /// A stub of the IBus daemon.
+    struct StubIBus {
+        engines: Vec<(&'static str, &'static str, &'static str)>,
+        active: usize,
+        current: String,
+    }
+
+    impl StubIBus {
+        fn desc(&self, name: &str) -> OwnedValue {
+            let &(name, longname, language) =
+                self.engines.iter().find(|(n, ..)| *n == name).unwrap();
+            let desc = StructureBuilder::new()
+                .add_field("IBusEngineDesc")
+                .add_field(HashMap::<String, Value<'_>>::new())
+                .add_field(name)
+                .add_field(longname)
+                .add_field("A stub engine")
+                .add_field(language);
+            let desc = ["GPL", "", "", "us"]
+                .into_iter()
+                .fold(desc, StructureBuilder::add_field)
+                .add_field(0_u32);
+            let desc = ["", "", "", "", "", "", "", ""]
+                .into_iter()
+                .fold(desc, StructureBuilder::add_field);
+            Value::Structure(desc.build().unwrap()).try_into().unwrap()
+        }
+    }
+
+    #[interface(name = "org.freedesktop.IBus")]
+    impl StubIBus {
+        #[zbus(property)]
+        fn global_engine(&self) -> OwnedValue {
+            self.desc(&self.current)
+        }
+
+        async fn set_global_engine(
+            &mut self,
+            name: String,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> zbus::fdo::Result<()> {
+            self.current.clone_from(&name);
+            Self::engine_changed(&emitter, &name).await?;
+            Ok(())
+        }
+
+        fn list_engines(&self) -> Vec<OwnedValue> {
+            self.engines.iter().map(|(n, ..)| self.desc(n)).collect()
+        }
+
+        fn list_active_engines(&self) -> Vec<OwnedValue> {
+            (self.engines[..self.active].iter())
+                .map(|(n, ..)| self.desc(n))
+                .collect()
+        }
+
+        #[zbus(signal, name = "GlobalEngineChanged")]
+        async fn engine_changed(emitter: &SignalEmitter<'_>, name: &str) -> zbus::Result<()>;
+    }
+
+    #[test]
+    #[ignore = "requires `dbus-daemon`"]
+    fn test_ibus_backend() {
+        let bus = TestBus::start();
+        let stub = StubIBus {
+            engines: vec![
+                ("xkb:us::eng", "English (US)", "en"),
+                ("libpinyin", "Intelligent Pinyin", "zh_CN"),
+                ("anthy", "Anthy", "ja"),
+            ],
+            active: 2,
+            current: "xkb:us::eng".into(),
+        };
+        let _service = (bus.builder())
+            .name(IBUS_SERVICE)
+            .unwrap()
+            .serve_at(IBUS_PATH, stub)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let backend = IBusBackend::with_connection(&bus.connect()).unwrap();
+        let engine_changes = backend.subscribe().unwrap();
+        assert_eq!(backend.current().unwrap(), "xkb:us::eng");
+
+        let enabled = backend.list(false).unwrap();
+        assert_eq!(enabled.len(), 2);
+        assert_eq!(enabled[1].name, "Intelligent Pinyin");
+        assert_eq!(enabled[1].languages, ["zh-CN"]);
+        let all = backend.list(true).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(!all[2].enabled);
+
+        assert!(backend.select("libpinyin").unwrap());
+        assert!(!backend.select("mozc-jp").unwrap());
+        assert_eq!(backend.current().unwrap(), "libpinyin");
+
+        let received = smol::block_on(future::or(
+            async { engine_changes.receiver().recv().await.ok() },
+            async {
+                Timer::after(Duration::from_secs(5)).await;
+                None
+            },
+        ));
+        assert_eq!(received.as_deref(), Some("libpinyin"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::ffi::c_void;
+
+use core_foundation::{
+    array::{CFArray, CFArrayRef},
+    base::{CFTypeID, FromVoid, OSStatus, TCFType, ToVoid},
+    boolean::{CFBoolean, CFBooleanRef},
+    data::CFDataRef,
+    declare_TCFType,
+    dictionary::{CFDictionary, CFDictionaryRef},
+    impl_TCFType,
+    string::{CFString, CFStringRef},
+};
+use objc2::rc::Retained;
+use objc2_foundation::NSDistributedNotificationCenter;
+use smol::channel;
+use tracing::info;
+
+use super::{InputSource, InputSourceBackend};
+use crate::{
+    error::Result, observer::notification::NotificationObserver, subscription::Subscription,
+};
+
+/// The [`InputSourceBackend`] backed by the Carbon Text Input Source
+/// Services.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TisBackend;
+
+impl InputSourceBackend for TisBackend {
+    // https://github.com/mzp/EmojiIM/issues/27#issue-1361876711
+    fn current(&self) -> Result<String> {
+        unsafe {
+            let src = TISInputSource::wrap_under_create_rule(TISCopyCurrentKeyboardInputSource());
+            let id = string_property(src.as_concrete_TypeRef(), kTISPropertyInputSourceID);
+            Ok(id.unwrap_or_default())
+        }
+    }
+
+    // https://github.com/daipeihust/im-select/blob/83046bb75333e58c9a7cbfbd055db6f360361781/macOS/im-select/im-select/main.m
+    fn select(&self, id: &str) -> Result<bool> {
+        if self.current()? == id {
+            return Ok(true);
+        }
+        info!("restoring current input source to `{id}`");
+        unsafe {
+            let filter = CFDictionary::from_CFType_pairs(&[(
+                CFString::from_void(kTISPropertyInputSourceID.cast()).clone(),
+                CFString::new(id),
+            )]);
+            let srcs = TISCreateInputSourceList(filter.to_untyped().to_void().cast(), false);
+            if srcs.is_null() {
+                return Ok(false);
+            }
+            let srcs = CFArray::<TISInputSource>::wrap_under_create_rule(srcs);
+            let Some(src) = srcs.get(0) else {
+                return Ok(false);
+            };
+            TISSelectInputSource(src.as_concrete_TypeRef());
+        }
+        Ok(true)
+    }
+
+    fn list(&self, include_all: bool) -> Result<Vec<InputSource>> {
+        unsafe {
+            let srcs = CFArray::<TISInputSource>::wrap_under_create_rule(TISCreateInputSourceList(
+                std::ptr::null(),
+                include_all,
+            ));
+            Ok(srcs
+                .iter()
+                .filter_map(|src| {
+                    let src = src.as_concrete_TypeRef();
+                    Some(InputSource {
+                        id: string_property(src, kTISPropertyInputSourceID)?,
+                        name: string_property(src, kTISPropertyLocalizedName).unwrap_or_default(),
+                        languages: strings_property(src, kTISPropertyInputSourceLanguages),
+                        category: string_property(src, kTISPropertyInputSourceCategory)
+                            .unwrap_or_default(),
+                        enabled: bool_property(src, kTISPropertyInputSourceIsEnabled),
+                        selectable: bool_property(src, kTISPropertyInputSourceIsSelectCapable),
+                    })
+                })
+                .collect())
+        }
+    }
+
+    fn subscribe(&self) -> Result<Subscription<String>> {
+        let (tx, rx) = channel::unbounded();
+        let observer = unsafe {
+            NotificationObserver::new(
+                Retained::cast_unchecked(NSDistributedNotificationCenter::defaultCenter()),
+                &*kTISNotifySelectedKeyboardInputSourceChanged.cast(),
+                move |_| {
+                    if let Ok(src) = Self.current() {
+                        _ = tx.try_send(src);
+                    }
+                },
+            )
+        };
+        Ok(Subscription::new(rx, observer))
+    }
+}
+
+/// Returns the string-typed property `key` of the input source `src`.
+unsafe fn string_property(src: TISInputSourceRef, key: CFStringRef) -> Option<String> {
+    let val = unsafe { TISGetInputSourceProperty(src, key) } as CFStringRef;
+    (!val.is_null()).then(|| unsafe { CFString::wrap_under_get_rule(val) }.to_string())
+}
+
+/// Returns the string-array-typed property `key` of the input source `src`.
+unsafe fn strings_property(src: TISInputSourceRef, key: CFStringRef) -> Vec<String> {
+    let val = unsafe { TISGetInputSourceProperty(src, key) } as CFArrayRef;
+    if val.is_null() {
+        return vec![];
+    }
+    let vals = unsafe { CFArray::<CFString>::wrap_under_get_rule(val) };
+    vals.iter().map(|s| s.to_string()).collect()
+}
+
+/// Returns the boolean-typed property `key` of the input source `src`.
+unsafe fn bool_property(src: TISInputSourceRef, key: CFStringRef) -> bool {
+    let val = unsafe { TISGetInputSourceProperty(src, key) } as CFBooleanRef;
+    !val.is_null() && unsafe { CFBoolean::wrap_under_get_rule(val) }.into()
+}
+
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct __TISInputSource(c_void);
+pub type TISInputSourceRef = *const __TISInputSource;
+
+declare_TCFType!(TISInputSource, TISInputSourceRef);
+impl_TCFType!(TISInputSource, TISInputSourceRef, TISInputSourceGetTypeID);
+
+#[link(name = "Carbon", kind = "framework")]
+unsafe extern "C" {
+    fn TISInputSourceGetTypeID() -> CFTypeID;
+    fn TISCopyCurrentKeyboardInputSource() -> TISInputSourceRef;
+    fn TISGetInputSourceProperty(source: TISInputSourceRef, propertyKey: CFStringRef) -> CFDataRef;
+    fn TISCreateInputSourceList(
+        properties: CFDictionaryRef,
+        includeAllInstalled: bool,
+    ) -> CFArrayRef;
+    fn TISSelectInputSource(source: TISInputSourceRef) -> OSStatus;
+
+    static kTISPropertyInputSourceID: CFStringRef;
+    static kTISPropertyLocalizedName: CFStringRef;
+    static kTISPropertyInputSourceLanguages: CFStringRef;
+    static kTISPropertyInputSourceCategory: CFStringRef;
+    static kTISPropertyInputSourceIsEnabled: CFStringRef;
+    static kTISPropertyInputSourceIsSelectCapable: CFStringRef;
+    pub static kTISNotifySelectedKeyboardInputSourceChanged: CFStringRef;
+}
@@ -1,32 +1,29 @@
-use std::{env, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{self, Path, PathBuf},
+    str::FromStr,
+};
 
-use clap::{Parser, Subcommand, builder::FalseyValueParser};
+use clap::{Args, Parser, Subcommand, builder::FalseyValueParser};
 use clavy::{
+    backend::{InputSource, InputSourceBackend, SourceTable},
+    config::Config,
+    daemon::Daemon,
+    doctor::{self, Probe, Report},
+    engine::Engine,
     error::{Error, Result},
-    observer::{
-        input_source::{
-            InputSourceState, input_source, kTISNotifySelectedKeyboardInputSourceChanged,
-            set_input_source,
-        },
-        notification::{
-            APP_HIDDEN_NOTIFICATION, FOCUSED_WINDOW_CHANGED_NOTIFICATION,
-            LOCAL_NOTIFICATION_CENTER, NotificationObserver,
-        },
-        workspace::WorkspaceObserver,
-    },
-    service::{self, Service},
-    util::{
-        bundle_id_from_current_app, bundle_id_from_notification, bundle_id_from_pid,
-        has_ax_privileges,
-    },
+    focus::FocusSource,
+    ipc::{self, Call, Client, Request, Response},
+    pattern::AppPattern,
+    recording::{self, Recorder, Replay},
+    service::{Drift, Service, ServiceSpec},
+    state::InputSourceState,
+    status::{DaemonInfo, Status},
+    util::{config_path, daemon_info_path, exe_path, socket_path, state_path},
 };
-use core_foundation::runloop::CFRunLoopRun;
-use libc::pid_t;
-use objc2::rc::Retained;
-use objc2_app_kit::{NSWorkspace, NSWorkspaceDidActivateApplicationNotification};
-use objc2_foundation::{NSDistributedNotificationCenter, NSNotification, NSNumber, NSString};
-use smol::channel;
-use tracing::{Level, debug, event, event_enabled, info, warn};
+use smol::channel::{self, Receiver};
+use tracing::{Level, info, warn};
 
 use crate::_built::GIT_VERSION;
 
@@ -52,25 +49,36 @@ pub struct Clavy {
     #[clap(long, env, value_parser = FalseyValueParser::new())]
     no_color: bool,
 
-    /// Comma-separated list of bundle IDs to detect popup windows from.
+    /// Comma-separated list of bundle IDs (or patterns thereof, e.g.
+    /// `com.raycast.*`) to detect popup windows from.
     #[clap(long, env = "CLAVY_DETECT_POPUP", value_delimiter = ',')]
-    detect_popup: Vec<String>,
+    detect_popup: Option<Vec<AppPattern>>,
+
+    /// Path to the configuration file [default: `~/.config/clavy/config.toml`].
+    #[clap(long, env = "CLAVY_CONFIG", global = true)]
+    config: Option<PathBuf>,
 }
 
-#[derive(Default, Copy, Clone, Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 pub enum Subcmd {
     /// Launch the daemon directly in the console.
-    #[default]
-    Launch,
+    Launch(LaunchOpts),
 
     /// Install the service.
-    Install,
+    Install(InstallOpts),
 
     /// Uninstall the service.
     Uninstall,
 
     /// Reinstall the service.
-    Reinstall,
+    Reinstall {
+        #[clap(flatten)]
+        opts: InstallOpts,
+        /// Show what would change in the installed service definition
+        /// instead of reinstalling the service.
+        #[clap(long)]
+        diff: bool,
+    },
 
     /// Start the service.
     Start,
@@ -80,6 +88,141 @@ pub enum Subcmd {
 
     /// Restart the service.
     Restart,
+
+    /// Report the status of the service and the daemon.
+    Status {
+        /// Print the status as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// List the input sources enabled on this system.
+    Sources {
+        /// Include all the installed input sources, not only the enabled ones.
+        #[clap(long)]
+        all: bool,
+        /// Print the input sources as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Check the environment for common problems.
+    Doctor {
+        /// Print the results as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Replay a recording made with `clavy launch --record` with a fake
+    /// input source backend, printing the decisions made for each event.
+    Replay {
+        /// Path to the recording.
+        file: PathBuf,
+        /// Path to a state file to start from instead of an empty state.
+        #[clap(long)]
+        state: Option<PathBuf>,
+    },
+
+    /// Inspect or edit the input sources remembered for each app.
+    #[clap(subcommand)]
+    Map(MapCmd),
+
+    /// Inspect the installed service.
+    #[clap(subcommand)]
+    Service(ServiceCmd),
+}
+
+impl Default for Subcmd {
+    fn default() -> Self {
+        Self::Launch(LaunchOpts::default())
+    }
+}
+
+#[derive(Default, Clone, Debug, Args)]
+pub struct LaunchOpts {
+    /// Record every event handled by the daemon to the given file in the JSON
+    /// Lines format, to be replayed with `clavy replay`.
+    #[clap(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+}
+
+/// The options recorded in the service definition upon installation.
+#[derive(Default, Clone, Debug, Args)]
+pub struct InstallOpts {
+    /// Path to the file to redirect the standard output of the service to.
+    #[clap(long)]
+    stdout_log: Option<PathBuf>,
+
+    /// Path to the file to redirect the standard error of the service to.
+    #[clap(long)]
+    stderr_log: Option<PathBuf>,
+
+    /// Niceness of the service [default: -20 on macOS].
+    #[clap(long, allow_negative_numbers = true)]
+    nice: Option<i32>,
+
+    /// Additional environment variable to pass to the service, e.g.
+    /// `RUST_LOG=debug` (can be repeated).
+    #[clap(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Do not start the service as soon as it is loaded, e.g. at login.
+    #[clap(long)]
+    no_run_at_load: bool,
+}
+
+impl InstallOpts {
+    /// Records the options in `spec`.
+    ///
+    /// Relative paths are resolved against the current directory, since the
+    /// service is not run from there.
+    fn apply(self, spec: &mut ServiceSpec) -> Result<()> {
+        let absolute = |path: Option<PathBuf>| path.map(path::absolute).transpose();
+        spec.stdout_path = absolute(self.stdout_log)?;
+        spec.stderr_path = absolute(self.stderr_log)?;
+        spec.config_path = absolute(spec.config_path.take())?;
+        spec.nice = self.nice;
+        spec.env.extend(self.env);
+        spec.run_at_load = !self.no_run_at_load;
+        Ok(())
+    }
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, val)) if !key.is_empty() => Ok((key.to_owned(), val.to_owned())),
+        _ => Err(format!("expected `KEY=VALUE`, found `{s}`")),
+    }
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum MapCmd {
+    /// List the input sources remembered for each app.
+    List,
+
+    /// Remember an input source for an app.
+    Set {
+        /// The bundle ID of the app.
+        app: String,
+        /// The ID of the input source, e.g. `com.apple.keylayout.ABC`.
+        input_source: String,
+    },
+
+    /// Forget the input source remembered for an app.
+    Forget {
+        /// The bundle ID of the app.
+        app: String,
+    },
+
+    /// Forget all the remembered input sources.
+    Clear,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ServiceCmd {
+    /// Check if the installed service has drifted from the current one, e.g.
+    /// after an upgrade.
+    Verify,
 }
 
 impl Clavy {
@@ -94,7 +237,8 @@ impl Clavy {
             )
             .init();
 
-        if !has_ax_privileges() {
+        #[cfg(target_os = "macos")]
+        if !clavy::util::has_ax_privileges() {
             warn!(
                 "it looks like required accessibility privileges have not been granted yet, and the service might exit immediately on startup..."
             );
@@ -103,11 +247,19 @@ impl Clavy {
             );
         }
 
-        let detect_popup = &self.detect_popup;
-        let service = || Service::try_new(service::ID, detect_popup);
+        let service_with = |opts: InstallOpts| {
+            let mut service = Service::try_new(self.detect_popup.as_deref(), self.config.clone())?;
+            opts.apply(&mut service.spec)?;
+            Ok::<_, Error>(service)
+        };
+        let service = || service_with(InstallOpts::default());
 
-        match self.subcmd.unwrap_or_default() {
-            Subcmd::Launch => match launch(detect_popup) {
+        match self.subcmd.clone().unwrap_or_default() {
+            Subcmd::Launch(opts) => match self.config_path().and_then(|path| {
+                let cfg = Config::load(&path)?;
+                let detect_popup = self.detect_popup.iter().flatten().cloned();
+                launch(detect_popup, cfg, path, opts.record)
+            }) {
                 Ok(()) => (),
                 // HACK: Exit with code 0 if the error is [`AxPrivilegesNotDetected`] to avoid
                 // spamming macOS' accessibility permissions dialog. Since a certain release of
@@ -116,155 +268,434 @@ impl Clavy {
                 Err(e @ Error::AxPrivilegesNotDetected) => warn!("{e}"),
                 Err(e) => return Err(e),
             },
-            Subcmd::Install => service()?.install()?,
+            Subcmd::Install(opts) => service_with(opts)?.install()?,
             Subcmd::Uninstall => service()?.uninstall()?,
-            Subcmd::Reinstall => service()?.reinstall()?,
+            Subcmd::Reinstall { opts, diff: true } => {
+                if let Some(diff) = service_with(opts)?.diff()? {
+                    print!("{diff}");
+                } else {
+                    info!("the installed service is up to date");
+                }
+            }
+            Subcmd::Reinstall { opts, diff: false } => service_with(opts)?.reinstall()?,
             Subcmd::Start => service()?.start()?,
             Subcmd::Stop => service()?.stop()?,
             Subcmd::Restart => service()?.restart()?,
+            Subcmd::Status { json } => {
+                let status = status(&service()?);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&status)?);
+                } else {
+                    println!("{status}");
+                }
+            }
+            Subcmd::Sources { all, json } => {
+                let srcs = input_sources(all)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&srcs)?);
+                } else {
+                    println!("{}", SourceTable(&srcs));
+                }
+            }
+            Subcmd::Doctor { json } => {
+                let report = Report::run(&SystemProbe {
+                    service: service()?,
+                    config_path: self.config_path()?,
+                });
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    println!("{report}");
+                }
+                if report.level() == doctor::Level::Fail {
+                    return Err(Error::ChecksFailed);
+                }
+            }
+            Subcmd::Replay { file, state } => {
+                let state = state.map_or_else(
+                    || Ok(InputSourceState::new()),
+                    |p| InputSourceState::restore(&p),
+                )?;
+                let cfg = Config::load(&self.config_path()?)?;
+                replay(&file, Engine::new(state).with_config(cfg))?;
+            }
+            Subcmd::Map(cmd) => map(cmd)?,
+            Subcmd::Service(ServiceCmd::Verify) => {
+                let drifts = service()?.verify()?;
+                if drifts.is_empty() {
+                    info!("the installed service is up to date");
+                    return Ok(());
+                }
+                for drift in &drifts {
+                    println!("{drift}");
+                }
+                warn!("run `clavy install` to repair the installed service");
+                return Err(Error::ServiceDrifted);
+            }
         }
         Ok(())
     }
+
+    /// Returns the path of the configuration file specified in the command
+    /// line, falling back to the default one.
+    fn config_path(&self) -> Result<PathBuf> {
+        self.config.clone().map_or_else(config_path, Ok)
+    }
 }
 
-#[allow(clippy::too_many_lines)]
-fn launch<S: AsRef<str>>(detect_popup: impl IntoIterator<Item = S>) -> Result<()> {
-    const NOTIF_NAME_LVL: Level = Level::DEBUG;
-    let activation_signal = |notif: &NSNotification, bundle_id: Retained<NSString>| {
-        (
-            event_enabled!(NOTIF_NAME_LVL).then(|| notif.name().to_string()),
-            bundle_id.to_string(),
-        )
-    };
+/// Collects the current [`Status`], leaving out the parts that are not
+/// available.
+///
+/// The running daemon is queried over the control socket if possible.
+fn status(service: &Service) -> Status {
+    let live = socket_path()
+        .and_then(|p| Client::connect(&p))
+        .and_then(|mut client| client.call(&Request::Status));
+    if let Ok(Response::Status {
+        pid,
+        started_at,
+        current_app,
+        input_source,
+        mappings,
+    }) = live
+    {
+        return Status {
+            service_path: service.definition_path().to_owned(),
+            installed: service.is_installed(),
+            loaded: service.is_loaded(),
+            daemon: Some(DaemonInfo { pid, started_at }.into()),
+            current_app,
+            input_source,
+            mappings,
+        };
+    }
 
-    if !has_ax_privileges() {
-        return Err(Error::AxPrivilegesNotDetected);
+    let state = state_path().and_then(|p| InputSourceState::restore(&p));
+    let daemon = daemon_info_path().and_then(|p| DaemonInfo::read(&p));
+    let (current_app, input_source) = current_focus();
+    Status {
+        service_path: service.definition_path().to_owned(),
+        installed: service.is_installed(),
+        loaded: service.is_loaded(),
+        daemon: (daemon.ok().flatten())
+            .filter(DaemonInfo::is_alive)
+            .map(Into::into),
+        current_app,
+        input_source,
+        mappings: state.map_or(0, |s| s.entries().len()),
     }
+}
 
-    info!("Hello from clavy!");
+/// The [`Probe`] into the current system.
+#[derive(Debug)]
+struct SystemProbe {
+    service: Service,
+    config_path: PathBuf,
+}
 
-    let input_source_state = InputSourceState::new();
-    let (activation_tx, activation_rx) = channel::unbounded();
-    let (input_source_tx, input_source_rx) = channel::unbounded();
-
-    let _workspace_observer = WorkspaceObserver::new(detect_popup);
-
-    let _focused_window_observer = NotificationObserver::new(
-        LOCAL_NOTIFICATION_CENTER.clone(),
-        &NSString::from_str(FOCUSED_WINDOW_CHANGED_NOTIFICATION),
-        {
-            let tx = activation_tx.clone();
-            move |notif| unsafe {
-                let notif = notif.as_ref();
-                let Some(pid) = notif.object() else {
-                    return;
-                };
-                let pid: pid_t = Retained::cast_unchecked::<NSNumber>(pid).as_i32();
-                let Some(bundle_id) = bundle_id_from_pid(pid) else {
-                    return;
-                };
-                let tx = tx.clone();
-                let signal = activation_signal(notif, bundle_id);
-                smol::spawn(async move { tx.send(signal).await.unwrap() }).detach();
-            }
-        },
-    );
-
-    let _app_hidden_observer = NotificationObserver::new(
-        LOCAL_NOTIFICATION_CENTER.clone(),
-        &NSString::from_str(APP_HIDDEN_NOTIFICATION),
-        {
-            let tx = activation_tx.clone();
-            move |notif| unsafe {
-                let notif = notif.as_ref();
-                let Some(bundle_id) = bundle_id_from_current_app() else {
-                    return;
-                };
-                let tx = tx.clone();
-                let signal = activation_signal(notif, bundle_id);
-                smol::spawn(async move { tx.send(signal).await.unwrap() }).detach();
-            }
-        },
-    );
-
-    let _did_activate_app_observer = unsafe {
-        NotificationObserver::new(
-            NSWorkspace::sharedWorkspace().notificationCenter(),
-            NSWorkspaceDidActivateApplicationNotification,
-            {
-                let tx = activation_tx;
-                move |notif| {
-                    let notif = notif.as_ref();
-                    let Some(bundle_id) = bundle_id_from_notification(notif) else {
-                        return;
-                    };
-                    let tx = tx.clone();
-                    let signal = activation_signal(notif, bundle_id);
-                    smol::spawn(async move { tx.send(signal).await.unwrap() }).detach();
-                }
-            },
-        )
+impl Probe for SystemProbe {
+    fn has_ax_privileges(&self) -> Option<bool> {
+        #[cfg(target_os = "macos")]
+        return Some(clavy::util::has_ax_privileges());
+        #[cfg(not(target_os = "macos"))]
+        None
+    }
+
+    fn exe_path(&self) -> Option<PathBuf> {
+        exe_path()
+    }
+
+    fn service_drifts(&self) -> Result<Vec<Drift>> {
+        self.service.verify()
+    }
+
+    fn config(&self) -> Result<Config> {
+        Config::load(&self.config_path)
+    }
+
+    fn mappings(&self) -> Result<BTreeMap<String, String>> {
+        Ok(InputSourceState::restore(&state_path()?)?.entries())
+    }
+
+    fn input_sources(&self) -> Result<Vec<InputSource>> {
+        input_sources(true)
+    }
+}
+
+/// Carries out `cmd` on the running daemon if any, or on the persisted state
+/// otherwise.
+fn map(cmd: MapCmd) -> Result<()> {
+    let Ok(mut client) = socket_path().and_then(|p| Client::connect(&p)) else {
+        // No daemon is running, so the persisted state can be edited in place.
+        return map_persisted(cmd);
+    };
+    let req = match cmd {
+        MapCmd::List => Request::ListMappings,
+        MapCmd::Set { app, input_source } => Request::SetMapping { app, input_source },
+        MapCmd::Forget { app } => Request::ForgetMapping { app },
+        MapCmd::Clear => Request::ClearMappings,
     };
+    match client.call(&req)? {
+        Response::Mappings { mappings } => print_mappings(&mappings),
+        Response::Done => (),
+        _ => return Err(Error::UnexpectedResponse),
+    }
+    Ok(())
+}
 
-    smol::spawn({
-        let input_source_state = input_source_state.clone();
-        async move {
-            let mut prev_app = None;
-            while let Ok((notif, curr_app)) = activation_rx.recv().await {
-                if prev_app.as_ref() == Some(&curr_app) {
-                    continue;
-                }
-                prev_app = Some(curr_app.clone());
-                event!(
-                    NOTIF_NAME_LVL,
-                    "detected activation of app `{curr_app}` via `{notif}`",
-                    // Unwrapping is safe here because we only send `Some()` with this level.
-                    notif = notif.unwrap()
-                );
-                if let Some(old_src) = input_source_state.load(&curr_app)
-                    && set_input_source(&old_src)
-                {
-                    continue;
-                }
-                let new_src = input_source();
-                debug!("registering input source for `{curr_app}` as `{new_src}`");
-                input_source_state.save(curr_app, new_src);
+fn map_persisted(cmd: MapCmd) -> Result<()> {
+    let state_path = state_path()?;
+    let state = InputSourceState::restore(&state_path)?;
+    match cmd {
+        MapCmd::List => {
+            print_mappings(&state.entries());
+            return Ok(());
+        }
+        MapCmd::Set { app, input_source } => state.save(app, input_source),
+        MapCmd::Forget { app } => {
+            if state.forget(&app).is_none() {
+                warn!("no input source has been remembered for `{app}`");
             }
         }
-    })
-    .detach();
+        MapCmd::Clear => state.clear(),
+    }
+    state.persist(&state_path)
+}
 
-    let _curr_input_source_observer = unsafe {
-        NotificationObserver::new(
-            Retained::cast_unchecked(NSDistributedNotificationCenter::defaultCenter()),
-            &*kTISNotifySelectedKeyboardInputSourceChanged.cast(),
-            move |_| {
-                smol::spawn({
-                    let tx = input_source_tx.clone();
-                    async move { tx.send(input_source()).await.unwrap() }
-                })
-                .detach();
-            },
-        )
+fn print_mappings(mappings: &BTreeMap<String, String>) {
+    for (app, input_source) in mappings {
+        println!("{app}\t{input_source}");
+    }
+}
+
+/// Feeds the recording at `path` through `engine`, printing the decisions
+/// made for each event.
+fn replay(path: &Path, engine: Engine) -> Result<()> {
+    let entries = recording::read(path)?;
+    let mut replay = Replay::new(engine, &entries);
+    for entry in &entries {
+        for decision in replay.step(entry)? {
+            println!("{decision}");
+        }
+    }
+    if let Some(decision) = replay.finish()? {
+        println!("{decision}");
+    }
+    Ok(())
+}
+
+/// The files advertising a running daemon, removed once dropped.
+#[derive(Debug)]
+struct DaemonFiles(Vec<PathBuf>);
+
+impl Drop for DaemonFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Sets up a [`Daemon`] driving `focus` and `backend`, restoring its state
+/// and serving its control socket.
+///
+/// The events handled by the daemon are recorded to `record` if specified.
+/// The returned receiver yields the requests from the control socket.
+fn setup_daemon<F: FocusSource, B: InputSourceBackend>(
+    focus: F,
+    backend: B,
+    config: Config,
+    config_path: PathBuf,
+    record: Option<PathBuf>,
+) -> Result<(Daemon<F, B>, Receiver<Call>, DaemonFiles)> {
+    let socket_path = socket_path()?;
+    let listener = ipc::bind(&socket_path)?;
+
+    info!("Hello from clavy!");
+    let daemon_info_path = daemon_info_path()?;
+    if let Err(e) = DaemonInfo::current().write(&daemon_info_path) {
+        warn!("failed to advertise daemon information: {e}");
+    }
+    let files = DaemonFiles(vec![socket_path, daemon_info_path]);
+
+    let state_path = state_path()?;
+    let daemon = match InputSourceState::restore(&state_path) {
+        Ok(state) => Daemon::new(Engine::new(state).with_config(config), focus, backend)
+            .with_state_path(state_path),
+        // Leave the state file untouched so that it can be recovered later on.
+        Err(e) => {
+            warn!(
+                "failed to restore state from `{}`, running without persistence: {e}",
+                state_path.display()
+            );
+            let engine = Engine::new(InputSourceState::new()).with_config(config);
+            Daemon::new(engine, focus, backend)
+        }
     };
 
+    let (call_tx, call_rx) = channel::unbounded();
     smol::spawn(async move {
-        let mut prev: Option<String> = None;
-        while let Ok(src) = input_source_rx.recv().await {
-            if prev.as_ref() == Some(&src) {
-                continue;
-            }
-            prev = Some(src.clone());
-            let Some(curr_app) = bundle_id_from_current_app() else {
-                warn!("failed to get bundle ID from current app");
-                continue;
-            };
-            debug!("updating input source for `{curr_app}` to `{src}`");
-            input_source_state.save(curr_app.to_string(), src);
+        if let Err(e) = ipc::serve(listener, call_tx).await {
+            warn!("control socket stopped working: {e}");
         }
     })
     .detach();
 
-    unsafe { CFRunLoopRun() };
-    Ok(())
+    let mut daemon = daemon.with_config_path(config_path);
+    if let Some(record) = record {
+        daemon = daemon.with_recorder(Recorder::create(&record)?);
+        info!("recording events to `{}`", record.display());
+    }
+    Ok((daemon, call_rx, files))
+}
+
+#[cfg(target_os = "linux")]
+use linux::{current_focus, input_sources, launch};
+#[cfg(target_os = "macos")]
+use macos::{current_focus, input_sources, launch};
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+const fn current_focus() -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+#[allow(clippy::unnecessary_wraps)]
+const fn input_sources(_include_all: bool) -> Result<Vec<InputSource>> {
+    Err(Error::UnsupportedPlatform)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+#[allow(clippy::unnecessary_wraps)]
+fn launch(
+    _detect_popup: impl IntoIterator<Item = AppPattern>,
+    _config: Config,
+    _config_path: PathBuf,
+    _record: Option<PathBuf>,
+) -> Result<()> {
+    Err(Error::UnsupportedPlatform)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::path::PathBuf;
+
+    use clavy::{
+        backend::{InputSourceBackend, fcitx::FcitxBackend, ibus::IBusBackend},
+        config::Config,
+        error::Result,
+        focus::{
+            FocusSource, hyprland::HyprlandFocusSource, sway::SwayFocusSource, x11::X11FocusSource,
+        },
+        pattern::AppPattern,
+    };
+
+    use super::{InputSource, setup_daemon};
+
+    pub fn launch(
+        // Popups are regular windows for Linux window managers, so there is no
+        // need to detect them specifically.
+        _detect_popup: impl IntoIterator<Item = AppPattern>,
+        config: Config,
+        config_path: PathBuf,
+        record: Option<PathBuf>,
+    ) -> Result<()> {
+        let (mut daemon, calls, _files) =
+            setup_daemon(focus_source(), backend()?, config, config_path, record)?;
+        let focus_sub = daemon.focus.subscribe()?;
+        let input_source_sub = daemon.backend.subscribe()?;
+        smol::block_on(daemon.run(focus_sub.receiver(), input_source_sub.receiver(), calls));
+        Ok(())
+    }
+
+    /// Returns the focus source for the window manager currently running,
+    /// falling back to the X server.
+    fn focus_source() -> Box<dyn FocusSource> {
+        if let Ok(hyprland) = HyprlandFocusSource::new() {
+            return Box::new(hyprland);
+        }
+        if let Ok(sway) = SwayFocusSource::new() {
+            return Box::new(sway);
+        }
+        Box::new(X11FocusSource::new())
+    }
+
+    /// Returns the backend of the input method framework currently running,
+    /// preferring Fcitx5 over IBus.
+    fn backend() -> Result<Box<dyn InputSourceBackend>> {
+        match FcitxBackend::new() {
+            Ok(fcitx) if fcitx.current().is_ok() => Ok(Box::new(fcitx)),
+            _ => Ok(Box::new(IBusBackend::new()?)),
+        }
+    }
+
+    pub fn input_sources(include_all: bool) -> Result<Vec<InputSource>> {
+        backend()?.list(include_all)
+    }
+
+    /// Returns the app currently in focus and the current input source.
+    pub fn current_focus() -> (Option<String>, Option<String>) {
+        (None, backend().and_then(|b| b.current()).ok())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use std::path::PathBuf;
+
+    use clavy::{
+        backend::{InputSourceBackend, tis::TisBackend},
+        config::Config,
+        error::{Error, Result},
+        focus::{FocusSource, appkit::AppKitFocusSource},
+        pattern::AppPattern,
+        util::has_ax_privileges,
+    };
+    use core_foundation::runloop::{CFRunLoopGetMain, CFRunLoopRun, CFRunLoopStop};
+
+    use super::{InputSource, setup_daemon};
+
+    pub fn launch(
+        detect_popup: impl IntoIterator<Item = AppPattern>,
+        config: Config,
+        config_path: PathBuf,
+        record: Option<PathBuf>,
+    ) -> Result<()> {
+        if !has_ax_privileges() {
+            return Err(Error::AxPrivilegesNotDetected);
+        }
+
+        let focus = AppKitFocusSource::new(
+            (detect_popup.into_iter()).chain(config.detect_popup.iter().cloned()),
+        )
+        .with_watch_titles(config.title_apps().cloned())
+        .with_per_window(config.per_window.iter().cloned());
+        let (mut daemon, calls, _files) =
+            setup_daemon(focus, TisBackend, config, config_path, record)?;
+        let focus_sub = daemon.focus.subscribe()?;
+        let input_source_sub = daemon.backend.subscribe()?;
+
+        let (focus_events, input_source_changes) =
+            (focus_sub.receiver(), input_source_sub.receiver());
+        smol::spawn(async move {
+            daemon.run(focus_events, input_source_changes, calls).await;
+            unsafe { CFRunLoopStop(CFRunLoopGetMain()) };
+        })
+        .detach();
+
+        unsafe { CFRunLoopRun() };
+        Ok(())
+    }
+
+    pub fn input_sources(include_all: bool) -> Result<Vec<InputSource>> {
+        TisBackend.list(include_all)
+    }
+
+    /// Returns the app currently in focus and the current input source.
+    pub fn current_focus() -> (Option<String>, Option<String>) {
+        (
+            AppKitFocusSource::default().current_app(),
+            TisBackend.current().ok(),
+        )
+    }
 }
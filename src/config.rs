@@ -0,0 +1,264 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, pattern::AppPattern};
+
+/// The user configuration, usually read from `~/.config/clavy/config.toml`.
+///
+/// # Example
+/// ```toml
+/// default = "com.apple.keylayout.ABC"
+/// detect-popup = ["com.raycast.macos"]
+/// ignore = ["com.apple.Terminal"]
+/// per-window = ["kitty"]
+/// settle-ms = 50
+///
+/// [[rules]]
+/// app = "com.microsoft.VSCode"
+/// input-source = "com.apple.keylayout.ABC"
+///
+/// [[rules]]
+/// app = "com.jetbrains.*"
+/// input-source = "com.apple.keylayout.ABC"
+///
+/// [[rules]]
+/// app = "com.google.Chrome"
+/// title = "*Gmail*"
+/// input-source = "com.apple.inputmethod.SCIM.ITABC"
+/// ```
+///
+/// All the app IDs and window titles in the configuration are
+/// [`AppPattern`]s.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// The input source to select for apps that have never been seen before.
+    pub default: Option<String>,
+
+    /// Additional bundle IDs to detect popup windows from.
+    pub detect_popup: Vec<AppPattern>,
+
+    /// The bundle IDs of the apps to leave alone.
+    pub ignore: Vec<AppPattern>,
+
+    /// The bundle IDs of the apps whose input sources are remembered for
+    /// each window rather than for the app as a whole.
+    pub per_window: Vec<AppPattern>,
+
+    /// The input sources pinned for specific apps.
+    ///
+    /// These always take precedence over the input sources learned at
+    /// runtime. If several rules match the same app, the ones matching the
+    /// title of its focused window are preferred over the others, then an
+    /// exact match is preferred over the others, otherwise the first match
+    /// wins.
+    pub rules: Vec<Rule>,
+
+    /// The settle window for bursts of focus changes in milliseconds, or `0`
+    /// to handle each focus change right away.
+    ///
+    /// All the focus changes within the window are coalesced into the last
+    /// one, e.g. the several notifications sent for a single Cmd-Tab.
+    pub settle_ms: u64,
+}
+
+/// An input source pinned for an app.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    /// The bundle ID of the app.
+    pub app: AppPattern,
+    /// The title of the focused window of the app, if the rule only applies
+    /// to some of its windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<AppPattern>,
+    /// The ID of the input source to always use for the app.
+    pub input_source: String,
+}
+
+impl Config {
+    /// Reads the configuration from `path`.
+    ///
+    /// The default configuration is returned if `path` does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns if the app identified by `app` should be left alone.
+    #[must_use]
+    pub fn is_ignored(&self, app: &str) -> bool {
+        self.ignore.iter().any(|pat| pat.matches(app))
+    }
+
+    #[must_use]
+    pub const fn settle_window(&self) -> Duration {
+        Duration::from_millis(self.settle_ms)
+    }
+
+    /// Returns if the input sources of the app identified by `app` are
+    /// remembered for each window.
+    #[must_use]
+    pub fn is_per_window(&self, app: &str) -> bool {
+        self.per_window.iter().any(|pat| pat.matches(app))
+    }
+
+    /// Returns the patterns of the apps whose rules depend on the title of
+    /// their focused windows.
+    pub fn title_apps(&self) -> impl Iterator<Item = &AppPattern> {
+        (self.rules.iter())
+            .filter(|r| r.title.is_some())
+            .map(|r| &r.app)
+    }
+
+    /// Returns the input source pinned for the app identified by `app` whose
+    /// focused window is titled `title`, if any.
+    ///
+    /// The rules with a title never match if `title` is unknown.
+    #[must_use]
+    pub fn pinned(&self, app: &str, title: Option<&str>) -> Option<&str> {
+        let (titled, untitled): (Vec<_>, Vec<_>) =
+            self.rules.iter().partition(|r| r.title.is_some());
+        let titled = titled.into_iter().filter(|r| {
+            (r.title.as_ref()).is_some_and(|pat| title.is_some_and(|title| pat.matches(title)))
+        });
+        AppPattern::find(titled, |r| &r.app, app)
+            .or_else(|| AppPattern::find(untitled, |r| &r.app, app))
+            .map(|r| r.input_source.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            default = "com.apple.keylayout.ABC"
+            ignore = ["com.apple.Terminal"]
+            per-window = ["kitty"]
+            settle-ms = 30
+
+            [[rules]]
+            app = "com.microsoft.VSCode"
+            input-source = "com.apple.keylayout.ABC"
+
+            [[rules]]
+            app = "com.tencent.xinWeChat"
+            input-source = "com.apple.inputmethod.SCIM.ITABC"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.default.as_deref(), Some("com.apple.keylayout.ABC"));
+        assert!(config.detect_popup.is_empty());
+        assert_eq!(config.settle_window(), Duration::from_millis(30));
+        assert!(config.is_ignored("com.apple.Terminal"));
+        assert!(!config.is_ignored("com.microsoft.VSCode"));
+        assert!(config.is_per_window("kitty"));
+        assert!(!config.is_per_window("com.apple.Terminal"));
+        assert_eq!(
+            config.pinned("com.tencent.xinWeChat", None),
+            Some("com.apple.inputmethod.SCIM.ITABC")
+        );
+        assert_eq!(config.pinned("com.apple.Safari", None), None);
+    }
+
+    #[test]
+    fn test_pattern_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            ignore = ["org.mozilla.*"]
+
+            [[rules]]
+            app = "/^com\\.jetbrains\\./"
+            input-source = "com.apple.keylayout.ABC"
+
+            [[rules]]
+            app = "com.jetbrains.pycharm"
+            input-source = "com.apple.keylayout.US"
+            "#,
+        )
+        .unwrap();
+        assert!(config.is_ignored("org.mozilla.nightly"));
+        assert_eq!(
+            config.pinned("com.jetbrains.goland", None),
+            Some("com.apple.keylayout.ABC")
+        );
+        assert_eq!(
+            config.pinned("com.jetbrains.pycharm", None),
+            Some("com.apple.keylayout.US")
+        );
+        assert!(toml::from_str::<Config>(r#"ignore = ["/(/"]"#).is_err());
+    }
+
+    #[test]
+    fn test_title_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            app = "com.google.Chrome"
+            input-source = "com.apple.keylayout.ABC"
+
+            [[rules]]
+            app = "com.google.*"
+            title = "*Gmail*"
+            input-source = "com.apple.inputmethod.SCIM.ITABC"
+
+            [[rules]]
+            app = "com.tinyspeck.slackmacgap"
+            title = "/#jp-team\\b/"
+            input-source = "com.apple.inputmethod.Kotoeri.RomajiTyping.Japanese"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.pinned("com.google.Chrome", Some("Inbox (3) - Gmail")),
+            Some("com.apple.inputmethod.SCIM.ITABC")
+        );
+        assert_eq!(
+            config.pinned("com.google.Chrome", Some("GitHub")),
+            Some("com.apple.keylayout.ABC")
+        );
+        assert_eq!(
+            config.pinned("com.google.Chrome", None),
+            Some("com.apple.keylayout.ABC")
+        );
+        assert_eq!(
+            config.pinned(
+                "com.tinyspeck.slackmacgap",
+                Some("#jp-team (Channel) - Slack")
+            ),
+            Some("com.apple.inputmethod.Kotoeri.RomajiTyping.Japanese")
+        );
+        assert_eq!(
+            config.pinned(
+                "com.tinyspeck.slackmacgap",
+                Some("#jp-teammates (Channel) - Slack")
+            ),
+            None
+        );
+        assert_eq!(config.pinned("com.tinyspeck.slackmacgap", None), None);
+        assert_eq!(
+            (config.title_apps().map(AppPattern::as_str)).collect::<Vec<_>>(),
+            ["com.google.*", "com.tinyspeck.slackmacgap"]
+        );
+    }
+
+    #[test]
+    fn test_reject_unknown_fields() {
+        assert!(toml::from_str::<Config>("defualt = \"com.apple.keylayout.ABC\"").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load(&dir.path().join("config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
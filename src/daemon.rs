@@ -0,0 +1,590 @@
+use std::{path::PathBuf, time::Instant};
+
+use smol::{Timer, channel::Receiver, future};
+use tracing::{debug, info, warn};
+
+use crate::{
+    backend::InputSourceBackend,
+    config::Config,
+    debounce::Debouncer,
+    engine::{Action, Engine, Event},
+    error::{Error, Result},
+    focus::{FocusEvent, FocusReason, FocusSource},
+    ipc::{Call, Request, Response},
+    recording::{Recorded, Recorder},
+    status::DaemonInfo,
+};
+
+/// The glue between a [`FocusSource`], an [`InputSourceBackend`] and the
+/// [`Engine`].
+#[derive(Debug)]
+pub struct Daemon<F, B> {
+    pub engine: Engine,
+    pub focus: F,
+    pub backend: B,
+    /// The path to persist the engine's state to whenever it changes.
+    pub state_path: Option<PathBuf>,
+    /// The path to reload the engine's configuration from upon request.
+    pub config_path: Option<PathBuf>,
+    /// The recorder to log the handled events to, if any.
+    pub recorder: Option<Recorder>,
+    info: DaemonInfo,
+}
+
+enum Signal {
+    Focus(FocusEvent),
+    Settled,
+    InputSource(String),
+    Call(Call),
+    Closed,
+}
+
+impl<F: FocusSource, B: InputSourceBackend> Daemon<F, B> {
+    pub fn new(engine: Engine, focus: F, backend: B) -> Self {
+        Self {
+            engine,
+            focus,
+            backend,
+            state_path: None,
+            config_path: None,
+            recorder: None,
+            info: DaemonInfo::current(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_state_path(self, state_path: impl Into<PathBuf>) -> Self {
+        Self {
+            state_path: Some(state_path.into()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_config_path(self, config_path: impl Into<PathBuf>) -> Self {
+        Self {
+            config_path: Some(config_path.into()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_recorder(self, recorder: Recorder) -> Self {
+        Self {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Handles the activation of an app, or the closing of one of its
+    /// windows.
+    pub fn handle_focus(&mut self, event: FocusEvent) -> Result<Vec<Action>> {
+        self.record_focus(&event);
+        self.apply_focus(event)
+    }
+
+    /// Writes `event` to the recorder, if any.
+    ///
+    /// This is done as soon as `event` is received, so that the recording
+    /// keeps the raw bursts of focus changes rather than the coalesced ones.
+    fn record_focus(&mut self, event: &FocusEvent) {
+        if self.recorder.is_none() {
+            return;
+        }
+        match self.backend.current() {
+            Ok(source) => self.record(|| Recorded::focus(event, &source)),
+            Err(e) => warn!("failed to record event: {e}"),
+        }
+    }
+
+    /// Handles a [`FocusEvent`] that has already been recorded.
+    fn apply_focus(&mut self, event: FocusEvent) -> Result<Vec<Action>> {
+        let source = self.backend.current()?;
+        let FocusEvent {
+            app,
+            reason,
+            window,
+            title,
+            ..
+        } = event;
+        if reason == FocusReason::WindowClosed {
+            let Some(window) = window else {
+                return Ok(vec![]);
+            };
+            return Ok(self.engine.handle(Event::WindowClosed { app, window }));
+        }
+        debug!("detected activation of app `{app}` via {reason}");
+        let event = Event::AppActivated {
+            app,
+            window,
+            title,
+            source,
+        };
+        let actions = self.engine.drive(&self.backend, event)?;
+        self.persist(&actions)?;
+        Ok(actions)
+    }
+
+    /// Handles the change of the current input source.
+    pub fn handle_input_source(&mut self, source: String) -> Result<Vec<Action>> {
+        let app = self.focus.current_app();
+        self.record(|| Recorded::InputSource {
+            app: app.clone(),
+            source: source.clone(),
+        });
+        let Some(app) = app else {
+            warn!("failed to get the app currently in focus");
+            return Ok(vec![]);
+        };
+        let actions = self
+            .engine
+            .drive(&self.backend, Event::InputSourceChanged { app, source })?;
+        self.persist(&actions)?;
+        Ok(actions)
+    }
+
+    /// Handles a [`Request`] from the control socket.
+    pub fn handle_request(&mut self, req: Request) -> Result<Response> {
+        debug!("received request {req:?}");
+        let state = self.engine.state();
+        match req {
+            Request::Status => {
+                return Ok(Response::Status {
+                    pid: self.info.pid,
+                    started_at: self.info.started_at,
+                    current_app: self.engine.current_app().map(ToOwned::to_owned),
+                    input_source: self.backend.current().ok(),
+                    mappings: state.entries().len(),
+                });
+            }
+            Request::ListMappings => {
+                return Ok(Response::Mappings {
+                    mappings: state.entries(),
+                });
+            }
+            Request::SetMapping { app, input_source } => state.save(app, input_source),
+            Request::ForgetMapping { app } => _ = state.forget(&app),
+            Request::ClearMappings => state.clear(),
+            Request::ReloadConfig => {
+                let config_path = self.config_path.as_ref().ok_or(Error::NoConfigPath)?;
+                self.engine.set_config(Config::load(config_path)?);
+                info!("reloaded configuration from `{}`", config_path.display());
+                return Ok(Response::Done);
+            }
+            Request::Shutdown => return Ok(Response::Done),
+        }
+        self.persist_state()?;
+        Ok(Response::Done)
+    }
+
+    /// Writes the event built by `event` to the recorder, if any.
+    fn record(&mut self, event: impl FnOnce() -> Recorded) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(event()) {
+            warn!("failed to record event: {e}");
+        }
+    }
+
+    /// Persists the engine's state if it has been changed by `actions`.
+    fn persist(&self, actions: &[Action]) -> Result<()> {
+        if actions.iter().any(|a| matches!(a, Action::Record { .. })) {
+            self.persist_state()?;
+        }
+        Ok(())
+    }
+
+    fn persist_state(&self) -> Result<()> {
+        if let Some(state_path) = &self.state_path {
+            self.engine.state().persist(state_path)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps handling the events from the given streams until `focus_events`
+    /// is closed or a [`Request::Shutdown`] is received from `calls`.
+    ///
+    /// The event streams are expected to come from the
+    /// [`FocusSource::subscribe`] and [`InputSourceBackend::subscribe`] methods
+    /// of this daemon's own sources. Pending input source changes take
+    /// precedence over focus changes, so that each change is attributed to the
+    /// app it has happened in.
+    ///
+    /// Bursts of focus changes are coalesced according to the settle window in
+    /// the engine's [`Config`].
+    pub async fn run(
+        &mut self,
+        focus_events: Receiver<FocusEvent>,
+        input_source_changes: Receiver<String>,
+        calls: Receiver<Call>,
+    ) {
+        let mut debouncer = Debouncer::new(self.engine.config().settle_window());
+        loop {
+            let deadline = debouncer.deadline();
+            let signal = future::or(
+                future::or(
+                    async { Signal::InputSource(recv_or_pending(&input_source_changes).await) },
+                    async { Signal::Call(recv_or_pending(&calls).await) },
+                ),
+                future::or(
+                    async {
+                        match deadline {
+                            Some(deadline) => _ = Timer::at(deadline).await,
+                            None => future::pending().await,
+                        }
+                        Signal::Settled
+                    },
+                    async {
+                        focus_events
+                            .recv()
+                            .await
+                            .map_or(Signal::Closed, Signal::Focus)
+                    },
+                ),
+            )
+            .await;
+            let res = match signal {
+                // Closing windows is not subject to coalescing, since it is not a
+                // change of focus.
+                Signal::Focus(event) if event.reason == FocusReason::WindowClosed => {
+                    self.handle_focus(event).map(drop)
+                }
+                Signal::Focus(event) => {
+                    self.record_focus(&event);
+                    debouncer.set_window(self.engine.config().settle_window());
+                    (debouncer.push(event, Instant::now()))
+                        .map_or(Ok(()), |event| self.apply_focus(event).map(drop))
+                }
+                Signal::Settled => (debouncer.poll(Instant::now()))
+                    .map_or(Ok(()), |event| self.apply_focus(event).map(drop)),
+                Signal::InputSource(src) => self.handle_input_source(src).map(drop),
+                Signal::Call((req, reply)) => {
+                    let shutdown = req == Request::Shutdown;
+                    let resp = self
+                        .handle_request(req)
+                        .unwrap_or_else(|e| Response::Error {
+                            message: e.to_string(),
+                        });
+                    _ = reply.send(resp).await;
+                    if shutdown {
+                        info!("shutting down as requested");
+                        break;
+                    }
+                    Ok(())
+                }
+                Signal::Closed => {
+                    if let Some(event) = debouncer.flush()
+                        && let Err(e) = self.apply_focus(event)
+                    {
+                        warn!("failed to handle event: {e}");
+                    }
+                    break;
+                }
+            };
+            if let Err(e) = res {
+                warn!("failed to handle event: {e}");
+            }
+        }
+        while let Ok(src) = input_source_changes.try_recv() {
+            if let Err(e) = self.handle_input_source(src) {
+                warn!("failed to handle event: {e}");
+            }
+        }
+    }
+}
+
+/// Receives a value from `rx`, or waits forever if `rx` has been closed.
+async fn recv_or_pending<T>(rx: &Receiver<T>) -> T {
+    match rx.recv().await {
+        Ok(t) => t,
+        Err(_) => future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use smol::channel;
+
+    use super::*;
+    use crate::{
+        backend::fake::FakeBackend,
+        focus::{Origin, scripted::ScriptedFocusSource},
+        ipc::{self, Client},
+        recording::{self, Replay},
+        state::InputSourceState,
+    };
+
+    const ABC: &str = "com.apple.keylayout.ABC";
+    const PINYIN: &str = "com.apple.inputmethod.SCIM.ITABC";
+    const SPOTLIGHT: &str = "com.apple.Spotlight";
+
+    fn run(
+        state: InputSourceState,
+        backend: &FakeBackend,
+        events: impl IntoIterator<Item = FocusEvent>,
+    ) -> Engine {
+        let focus = ScriptedFocusSource::new(events);
+        let focus_sub = focus.subscribe().unwrap();
+        let input_source_sub = backend.subscribe().unwrap();
+        let mut daemon = Daemon::new(Engine::new(state), focus, backend.clone());
+        smol::block_on(daemon.run(
+            focus_sub.receiver(),
+            input_source_sub.receiver(),
+            channel::unbounded().1,
+        ));
+        daemon.engine
+    }
+
+    #[test]
+    fn test_popup_window() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save(SPOTLIGHT.into(), ABC.into());
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        _ = run(
+            state,
+            &backend,
+            [
+                FocusEvent::new("foo", FocusReason::AppActivated),
+                FocusEvent::new("foo", FocusReason::FocusedWindowChanged),
+                FocusEvent::new(SPOTLIGHT, FocusReason::FocusedWindowChanged),
+                FocusEvent::new("foo", FocusReason::FocusedWindowChanged),
+            ],
+        );
+        assert_eq!(backend.selections(), [PINYIN, ABC, PINYIN]);
+    }
+
+    #[test]
+    fn test_app_hidden() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), ABC.into());
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let engine = run(
+            state,
+            &backend,
+            [
+                FocusEvent::new("foo", FocusReason::AppActivated),
+                FocusEvent::new("bar", FocusReason::AppActivated),
+                FocusEvent::new("foo", FocusReason::AppHidden),
+            ],
+        );
+        assert_eq!(backend.selections(), [PINYIN, ABC, PINYIN]);
+        // The echoes of the selections must be attributed to the apps they have
+        // been made for.
+        assert_eq!(engine.state().load("foo").as_deref(), Some(PINYIN));
+        assert_eq!(engine.state().load("bar").as_deref(), Some(ABC));
+    }
+
+    #[test]
+    fn test_late_echoes() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), ABC.into());
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let focus = ScriptedFocusSource::new([
+            FocusEvent::new("foo", FocusReason::AppActivated),
+            FocusEvent::new("bar", FocusReason::AppActivated),
+        ]);
+        let focus_sub = focus.subscribe().unwrap();
+        let input_source_sub = backend.subscribe().unwrap();
+        let mut daemon = Daemon::new(Engine::new(state), focus, backend.clone());
+
+        // Both apps are activated before the echo of the first selection
+        // arrives, so all the echoes are received while `bar` is in focus.
+        let focus_rx = focus_sub.receiver();
+        while let Ok(event) = focus_rx.try_recv() {
+            daemon.handle_focus(event).unwrap();
+        }
+        let echoes = input_source_sub.receiver();
+        while let Ok(src) = echoes.try_recv() {
+            assert_eq!(daemon.handle_input_source(src).unwrap(), []);
+        }
+        assert_eq!(backend.selections(), [PINYIN, ABC]);
+        assert_eq!(daemon.engine.state().load("foo").as_deref(), Some(PINYIN));
+        assert_eq!(daemon.engine.state().load("bar").as_deref(), Some(ABC));
+    }
+
+    #[test]
+    fn test_settle_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording_path = dir.path().join("events.jsonl");
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), ABC.into());
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        // A single app switch flapping between two apps, with a settle window
+        // long enough never to elapse in this test, so that the burst is only
+        // handled when the stream is closed. The timing itself is covered by
+        // the tests of `Debouncer`.
+        let focus = ScriptedFocusSource::new([
+            FocusEvent::new("bar", FocusReason::AppActivated),
+            FocusEvent::new("foo", FocusReason::FocusedWindowChanged),
+            FocusEvent::new("bar", FocusReason::AppHidden),
+            FocusEvent::new("foo", FocusReason::AppActivated),
+        ]);
+        let config = Config {
+            settle_ms: 60_000,
+            ..Config::default()
+        };
+        let focus_sub = focus.subscribe().unwrap();
+        let input_source_sub = backend.subscribe().unwrap();
+        let mut daemon = Daemon::new(
+            Engine::new(state).with_config(config),
+            focus,
+            backend.clone(),
+        )
+        .with_recorder(Recorder::create(&recording_path).unwrap());
+        smol::block_on(daemon.run(
+            focus_sub.receiver(),
+            input_source_sub.receiver(),
+            channel::unbounded().1,
+        ));
+        drop(daemon);
+        assert_eq!(backend.selections(), [PINYIN]);
+
+        // The whole burst is recorded rather than the coalesced event.
+        let entries = recording::read(&recording_path).unwrap();
+        let focus_entries = (entries.iter())
+            .filter(|e| matches!(e.event, Recorded::Focus { .. }))
+            .count();
+        assert_eq!(focus_entries, 4);
+    }
+
+    #[test]
+    fn test_persist_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let mut daemon = Daemon::new(
+            Engine::new(InputSourceState::new()),
+            ScriptedFocusSource::new([FocusEvent::new("foo", FocusReason::AppActivated)]),
+            backend.clone(),
+        )
+        .with_state_path(&state_path);
+        let focus_sub = daemon.focus.subscribe().unwrap();
+        let input_source_sub = backend.subscribe().unwrap();
+        smol::block_on(daemon.run(
+            focus_sub.receiver(),
+            input_source_sub.receiver(),
+            channel::unbounded().1,
+        ));
+
+        let restored = InputSourceState::restore(&state_path).unwrap();
+        assert_eq!(restored.load("foo").as_deref(), Some(ABC));
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording_path = dir.path().join("events.jsonl");
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let focus = ScriptedFocusSource::new([
+            FocusEvent::new("foo", FocusReason::AppActivated).with_origin("activate", Some(1)),
+            FocusEvent::new("bar", FocusReason::AppActivated),
+            FocusEvent::new("foo", FocusReason::AppHidden),
+        ]);
+        let focus_sub = focus.subscribe().unwrap();
+        let input_source_sub = backend.subscribe().unwrap();
+        let mut daemon = Daemon::new(Engine::new(state), focus, backend.clone())
+            .with_recorder(Recorder::create(&recording_path).unwrap());
+        smol::block_on(daemon.run(
+            focus_sub.receiver(),
+            input_source_sub.receiver(),
+            channel::unbounded().1,
+        ));
+        drop(daemon);
+
+        let entries = recording::read(&recording_path).unwrap();
+        assert_eq!(
+            entries[0].event,
+            Recorded::Focus {
+                app: "foo".into(),
+                reason: FocusReason::AppActivated,
+                window: None,
+                title: None,
+                origin: Some(Origin {
+                    notification: "activate".into(),
+                    pid: Some(1),
+                }),
+                source: ABC.into(),
+            }
+        );
+        assert!(
+            (entries.iter()).any(
+                |e| matches!(&e.event, Recorded::InputSource { source, .. } if source == PINYIN)
+            )
+        );
+
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        let mut replay = Replay::new(Engine::new(state), &entries);
+        for entry in &entries {
+            replay.step(entry).unwrap();
+        }
+        replay.finish().unwrap();
+        assert_eq!(replay.backend.selections(), backend.selections());
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("clavy.sock");
+        let config_path = dir.path().join("config.toml");
+        fs::write(&config_path, "ignore = [\"foo\"]").unwrap();
+
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let mut daemon = Daemon::new(
+            Engine::new(InputSourceState::new()),
+            ScriptedFocusSource::new([]),
+            backend.clone(),
+        )
+        .with_state_path(dir.path().join("state.json"))
+        .with_config_path(&config_path);
+        let input_source_sub = backend.subscribe().unwrap();
+        let (focus_tx, focus_rx) = channel::unbounded();
+        let (call_tx, call_rx) = channel::unbounded();
+
+        let listener = ipc::bind(&socket_path).unwrap();
+        let client = thread::spawn(move || {
+            let mut client = Client::connect(&socket_path).unwrap();
+            let mut call = |req| client.call(&req).unwrap();
+            call(Request::SetMapping {
+                app: "foo".into(),
+                input_source: PINYIN.into(),
+            });
+            call(Request::SetMapping {
+                app: "bar".into(),
+                input_source: ABC.into(),
+            });
+            call(Request::ForgetMapping { app: "bar".into() });
+            let Response::Mappings { mappings } = call(Request::ListMappings) else {
+                panic!("unexpected response");
+            };
+            assert_eq!(mappings, [("foo".into(), PINYIN.into())].into());
+            let Response::Status { mappings, .. } = call(Request::Status) else {
+                panic!("unexpected response");
+            };
+            assert_eq!(mappings, 1);
+            assert_eq!(call(Request::ReloadConfig), Response::Done);
+            call(Request::Shutdown);
+        });
+        smol::block_on(async {
+            smol::spawn(ipc::serve(listener, call_tx)).detach();
+            daemon
+                .run(focus_rx, input_source_sub.receiver(), call_rx)
+                .await;
+        });
+        drop(focus_tx);
+        client.join().unwrap();
+
+        assert!(daemon.engine.config().is_ignored("foo"));
+        let restored = InputSourceState::restore(&dir.path().join("state.json")).unwrap();
+        assert_eq!(restored.entries(), [("foo".into(), PINYIN.into())].into());
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+
+/// Coalesces bursts of values into the last value of each burst.
+///
+/// A burst settles once no new value has been pushed for the duration of the
+/// settle window, or once it has lasted for twice that duration, so that a
+/// constant stream of values cannot postpone the outcome forever.
+///
+/// The current time is always passed in by the caller, so the debouncer can
+/// be driven by a virtual clock as well.
+#[derive(Clone, Debug)]
+pub struct Debouncer<T> {
+    window: Duration,
+    pending: Option<Pending<T>>,
+}
+
+#[derive(Clone, Debug)]
+struct Pending<T> {
+    value: T,
+    started_at: Instant,
+    deadline: Instant,
+}
+
+impl<T> Debouncer<T> {
+    #[must_use]
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: None,
+        }
+    }
+
+    /// Changes the settle window, which takes effect from the next push on.
+    pub const fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Pushes `value` received at `now`, superseding the pending value if
+    /// any.
+    ///
+    /// Returns `value` right away if the settle window is zero.
+    pub fn push(&mut self, value: T, now: Instant) -> Option<T> {
+        if self.window.is_zero() {
+            self.pending = None;
+            return Some(value);
+        }
+        let started_at = self.pending.as_ref().map_or(now, |p| p.started_at);
+        let deadline = (now + self.window).min(started_at + 2 * self.window);
+        self.pending = Some(Pending {
+            value,
+            started_at,
+            deadline,
+        });
+        None
+    }
+
+    /// Returns the time at which the pending value settles, if any.
+    #[must_use]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|p| p.deadline)
+    }
+
+    /// Takes the pending value if it has settled by `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<T> {
+        if self.deadline()? > now {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// Takes the pending value regardless of whether it has settled.
+    pub fn flush(&mut self) -> Option<T> {
+        self.pending.take().map(|p| p.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A virtual clock starting at an arbitrary instant.
+    struct Clock(Instant);
+
+    impl Clock {
+        fn at(&self, millis: u64) -> Instant {
+            self.0 + Duration::from_millis(millis)
+        }
+    }
+
+    #[test]
+    fn test_zero_window() {
+        let clock = Clock(Instant::now());
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        assert_eq!(debouncer.push("foo", clock.at(0)), Some("foo"));
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn test_coalesce_burst() {
+        let clock = Clock(Instant::now());
+        let mut debouncer = Debouncer::new(Duration::from_millis(50));
+        assert_eq!(debouncer.push("foo", clock.at(0)), None);
+        assert_eq!(debouncer.push("bar", clock.at(5)), None);
+        assert_eq!(debouncer.push("foo", clock.at(10)), None);
+        assert_eq!(debouncer.deadline(), Some(clock.at(60)));
+        assert_eq!(debouncer.poll(clock.at(59)), None);
+        assert_eq!(debouncer.poll(clock.at(60)), Some("foo"));
+        assert_eq!(debouncer.poll(clock.at(1000)), None);
+
+        // A new burst starts afterwards.
+        assert_eq!(debouncer.push("bar", clock.at(1000)), None);
+        assert_eq!(debouncer.poll(clock.at(1050)), Some("bar"));
+    }
+
+    #[test]
+    fn test_bounded_delay() {
+        let clock = Clock(Instant::now());
+        let mut debouncer = Debouncer::new(Duration::from_millis(50));
+        for t in (0..=90).step_by(30) {
+            assert_eq!(debouncer.push(t, clock.at(t)), None);
+        }
+        assert_eq!(debouncer.deadline(), Some(clock.at(100)));
+        assert_eq!(debouncer.poll(clock.at(100)), Some(90));
+    }
+
+    #[test]
+    fn test_flush() {
+        let clock = Clock(Instant::now());
+        let mut debouncer = Debouncer::new(Duration::from_millis(50));
+        _ = debouncer.push("foo", clock.at(0));
+        assert_eq!(debouncer.flush(), Some("foo"));
+        assert_eq!(debouncer.flush(), None);
+
+        _ = debouncer.push("foo", clock.at(0));
+        debouncer.set_window(Duration::ZERO);
+        assert_eq!(debouncer.push("bar", clock.at(1)), Some("bar"));
+        assert_eq!(debouncer.flush(), None);
+    }
+}
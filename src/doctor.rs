@@ -0,0 +1,424 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    backend::InputSource,
+    config::Config,
+    error::{Error, Result},
+    service::Drift,
+};
+
+/// The facts about the environment that `clavy doctor` looks into.
+///
+/// These are abstracted away so that the checks can be run against fake
+/// environments as well.
+pub trait Probe {
+    /// Returns if the accessibility privileges have been granted, or `None`
+    /// if they are irrelevant on this platform.
+    fn has_ax_privileges(&self) -> Option<bool>;
+
+    /// Returns the path of the current executable.
+    fn exe_path(&self) -> Option<PathBuf>;
+
+    /// Returns how the installed service has drifted from the current one, as
+    /// in [`crate::service::Service::verify`].
+    fn service_drifts(&self) -> Result<Vec<Drift>>;
+
+    /// Returns the user configuration.
+    fn config(&self) -> Result<Config>;
+
+    /// Returns the input sources remembered for each app.
+    fn mappings(&self) -> Result<BTreeMap<String, String>>;
+
+    /// Returns all the input sources installed on this system.
+    fn input_sources(&self) -> Result<Vec<InputSource>>;
+}
+
+/// The outcome of a [`Check`], from the best to the worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+        })
+    }
+}
+
+/// A single item of the checklist run by `clavy doctor`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Check {
+    /// What has been checked, e.g. `accessibility`.
+    pub name: &'static str,
+    pub level: Level,
+    /// What has been found out.
+    pub message: String,
+    /// How to fix the problem, if any.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            level: Level::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            level: Level::Warn,
+            hint: Some(hint.into()),
+            ..Self::pass(name, message)
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            level: Level::Fail,
+            ..Self::warn(name, message, hint)
+        }
+    }
+}
+
+/// The results of all the checks run by `clavy doctor`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Report(pub Vec<Check>);
+
+impl Report {
+    /// Runs the checklist against the environment described by `probe`.
+    pub fn run(probe: &impl Probe) -> Self {
+        let mut checks = vec![];
+        if let Some(ax) = probe.has_ax_privileges() {
+            checks.push(check_ax_privileges(ax));
+        }
+        checks.push(check_exe_path(probe.exe_path()));
+        checks.push(check_service(probe.service_drifts()));
+
+        let config = probe.config();
+        let mappings = probe.mappings();
+        checks.push(match &config {
+            Ok(_) => Check::pass("config", "the configuration is valid"),
+            Err(e) => Check::fail(
+                "config",
+                format!("failed to load the configuration: {e}"),
+                "fix the configuration file, see the README for its format",
+            ),
+        });
+        checks.extend(check_input_sources(
+            config.ok().as_ref(),
+            mappings,
+            probe.input_sources(),
+        ));
+        Self(checks)
+    }
+
+    /// Returns the worst [`Level`] among the checks.
+    #[must_use]
+    pub fn level(&self) -> Level {
+        (self.0.iter().map(|c| c.level).max()).unwrap_or(Level::Pass)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, check) in self.0.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "[{}] {}: {}", check.level, check.name, check.message)?;
+            if let Some(hint) = &check.hint {
+                write!(f, "\n       hint: {hint}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_ax_privileges(granted: bool) -> Check {
+    if granted {
+        Check::pass(
+            "accessibility",
+            "accessibility privileges have been granted",
+        )
+    } else {
+        Check::fail(
+            "accessibility",
+            "accessibility privileges have not been granted",
+            "allow clavy in `System Settings > Privacy & Security > Accessibility`, then run `clavy restart`",
+        )
+    }
+}
+
+fn check_exe_path(path: Option<PathBuf>) -> Check {
+    path.map_or_else(
+        || {
+            Check::fail(
+                "executable",
+                Error::FaultyExePath.to_string(),
+                "run clavy from its installed location instead",
+            )
+        },
+        |path| Check::pass("executable", format!("running from `{}`", path.display())),
+    )
+}
+
+fn check_service(drifts: Result<Vec<Drift>>) -> Check {
+    match drifts {
+        Ok(drifts) if drifts.is_empty() => Check::pass("service", "the service is up to date"),
+        Ok(drifts) => Check::fail(
+            "service",
+            format!(
+                "the installed service has drifted: {}",
+                (drifts.iter().map(ToString::to_string))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            "run `clavy install` to repair it, then `clavy restart`",
+        ),
+        Err(e @ Error::ServiceNotInstalled(_)) => {
+            Check::warn("service", e.to_string(), "run `clavy install`")
+        }
+        Err(e) => Check::fail(
+            "service",
+            format!("failed to verify the installed service: {e}"),
+            "run `clavy reinstall`",
+        ),
+    }
+}
+
+/// Checks that the input sources referred to by the configuration and the
+/// remembered ones are installed.
+fn check_input_sources(
+    config: Option<&Config>,
+    mappings: Result<BTreeMap<String, String>>,
+    sources: Result<Vec<InputSource>>,
+) -> Vec<Check> {
+    let sources = match sources {
+        Ok(sources) => sources,
+        Err(e) => {
+            return vec![Check::warn(
+                "input sources",
+                format!("failed to list the installed input sources: {e}"),
+                "make sure the input method framework is running",
+            )];
+        }
+    };
+    let unknown = |ids: &mut dyn Iterator<Item = &str>| {
+        let mut unknown = vec![];
+        for id in ids {
+            if !sources.iter().any(|src| src.id == id) && !unknown.contains(&id) {
+                unknown.push(id);
+            }
+        }
+        join_ids(&unknown)
+    };
+
+    let mut checks = vec![];
+    if let Some(config) = config {
+        let mut ids = (config.default.iter().map(String::as_str))
+            .chain(config.rules.iter().map(|r| r.input_source.as_str()));
+        let unknown = unknown(&mut ids);
+        checks.push(if unknown.is_empty() {
+            Check::pass(
+                "configured input sources",
+                "all the configured input sources are installed",
+            )
+        } else {
+            Check::fail(
+                "configured input sources",
+                format!("unknown input sources in the configuration: {unknown}"),
+                "run `clavy sources --all` to find out the right IDs",
+            )
+        });
+    }
+    checks.push(match mappings {
+        Ok(mappings) => {
+            let unknown = unknown(&mut mappings.values().map(String::as_str));
+            if unknown.is_empty() {
+                Check::pass(
+                    "remembered input sources",
+                    "all the remembered input sources are installed",
+                )
+            } else {
+                Check::warn(
+                    "remembered input sources",
+                    format!("unknown input sources have been remembered: {unknown}"),
+                    "run `clavy map list` and `clavy map forget` the apps concerned",
+                )
+            }
+        }
+        Err(e) => Check::warn(
+            "remembered input sources",
+            format!("failed to restore the remembered input sources: {e}"),
+            "run `clavy map clear` to start over",
+        ),
+    });
+    checks
+}
+
+fn join_ids(ids: &[&str]) -> String {
+    (ids.iter().map(|id| format!("`{id}`")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Rule;
+
+    #[derive(Debug)]
+    struct FakeProbe {
+        ax: Option<bool>,
+        exe_path: Option<PathBuf>,
+        drifts: fn() -> Result<Vec<Drift>>,
+        config: Config,
+        mappings: BTreeMap<String, String>,
+        sources: Vec<&'static str>,
+    }
+
+    impl Default for FakeProbe {
+        fn default() -> Self {
+            Self {
+                ax: Some(true),
+                exe_path: Some("/opt/homebrew/bin/clavy".into()),
+                drifts: || Ok(vec![]),
+                config: Config::default(),
+                mappings: BTreeMap::new(),
+                sources: vec!["com.apple.keylayout.ABC", "com.apple.keylayout.US"],
+            }
+        }
+    }
+
+    impl Probe for FakeProbe {
+        fn has_ax_privileges(&self) -> Option<bool> {
+            self.ax
+        }
+
+        fn exe_path(&self) -> Option<PathBuf> {
+            self.exe_path.clone()
+        }
+
+        fn service_drifts(&self) -> Result<Vec<Drift>> {
+            (self.drifts)()
+        }
+
+        fn config(&self) -> Result<Config> {
+            Ok(self.config.clone())
+        }
+
+        fn mappings(&self) -> Result<BTreeMap<String, String>> {
+            Ok(self.mappings.clone())
+        }
+
+        fn input_sources(&self) -> Result<Vec<InputSource>> {
+            Ok((self.sources.iter())
+                .map(|&id| InputSource {
+                    id: id.into(),
+                    ..InputSource::default()
+                })
+                .collect())
+        }
+    }
+
+    fn levels(report: &Report) -> Vec<(&'static str, Level)> {
+        report.0.iter().map(|c| (c.name, c.level)).collect()
+    }
+
+    #[test]
+    fn test_healthy() {
+        let report = Report::run(&FakeProbe::default());
+        assert_eq!(report.level(), Level::Pass);
+        assert!(report.0.iter().all(|c| c.hint.is_none()));
+
+        let report = Report::run(&FakeProbe {
+            ax: None,
+            ..FakeProbe::default()
+        });
+        assert!(report.0.iter().all(|c| c.name != "accessibility"));
+    }
+
+    #[test]
+    fn test_problems() {
+        let probe = FakeProbe {
+            ax: Some(false),
+            exe_path: None,
+            drifts: || Ok(vec![Drift::Format]),
+            config: Config {
+                default: Some("com.apple.keylayout.ABC".into()),
+                rules: vec![Rule {
+                    app: "com.tencent.xinWeChat".parse().unwrap(),
+                    title: None,
+                    input_source: "com.apple.inputmethod.SCIM.ITABC".into(),
+                }],
+                ..Config::default()
+            },
+            mappings: BTreeMap::from([(
+                "com.apple.Safari".into(),
+                "com.apple.keylayout.Dvorak".into(),
+            )]),
+            ..FakeProbe::default()
+        };
+        let report = Report::run(&probe);
+        assert_eq!(report.level(), Level::Fail);
+        assert_eq!(
+            levels(&report),
+            [
+                ("accessibility", Level::Fail),
+                ("executable", Level::Fail),
+                ("service", Level::Fail),
+                ("config", Level::Pass),
+                ("configured input sources", Level::Fail),
+                ("remembered input sources", Level::Warn),
+            ]
+        );
+        assert!(
+            report.0[4]
+                .message
+                .contains("`com.apple.inputmethod.SCIM.ITABC`")
+        );
+        assert!(!report.0[4].message.contains("ABC`,"));
+        assert!(report.0[5].message.contains("`com.apple.keylayout.Dvorak`"));
+    }
+
+    #[test]
+    fn test_service_not_installed() {
+        let report = Report::run(&FakeProbe {
+            drifts: || Err(Error::ServiceNotInstalled("/tmp/clavy.plist".into())),
+            ..FakeProbe::default()
+        });
+        assert_eq!(report.level(), Level::Warn);
+        assert_eq!(report.0[2].hint.as_deref(), Some("run `clavy install`"));
+    }
+
+    #[test]
+    fn test_report_output() {
+        let report = Report(vec![
+            Check::pass("executable", "running from `/usr/bin/clavy`"),
+            Check::warn("service", "not installed", "run `clavy install`"),
+        ]);
+        assert_eq!(
+            report.to_string(),
+            "\
+[pass] executable: running from `/usr/bin/clavy`
+[warn] service: not installed
+       hint: run `clavy install`"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json[1]["level"], "warn");
+        assert_eq!(json[0]["hint"], serde_json::Value::Null);
+    }
+}
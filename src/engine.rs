@@ -0,0 +1,685 @@
+use std::{collections::VecDeque, mem};
+
+use tracing::debug;
+
+use crate::{backend::InputSourceBackend, config::Config, error::Result, state::InputSourceState};
+
+/// An event to be consumed by the [`Engine`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The app `app` has been activated while `source` was the current input
+    /// source, focusing the window identified by `window` and titled `title`
+    /// if known.
+    ///
+    /// This is also the event for a change of the title of the focused window
+    /// of `app`.
+    AppActivated {
+        app: String,
+        window: Option<String>,
+        title: Option<String>,
+        source: String,
+    },
+
+    /// The window identified by `window` of the app `app` has been closed.
+    WindowClosed { app: String, window: String },
+
+    /// The current input source has been changed to `source` while `app` was
+    /// in focus.
+    InputSourceChanged { app: String, source: String },
+
+    /// Selecting an input source for `app` has failed, leaving `source` as
+    /// the current one.
+    SelectionFailed { app: String, source: String },
+}
+
+/// An action emitted by the [`Engine`] in response to an [`Event`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Select `source` as the current input source on behalf of `app`.
+    ///
+    /// If the selection fails, the driver is expected to report it back with
+    /// [`Event::SelectionFailed`].
+    Select { app: String, source: String },
+
+    /// `source` has been recorded as the input source of `app`.
+    ///
+    /// # Note
+    /// The engine's [`InputSourceState`] has already been updated when this
+    /// action is emitted.
+    Record { app: String, source: String },
+}
+
+/// The platform-agnostic input source switching logic.
+///
+/// The engine has no knowledge of how events are collected or how actions
+/// are carried out, so it can be driven by a scripted event stream as well
+/// as by the actual system notifications.
+///
+/// The rules in the engine's [`Config`] take precedence over the input
+/// sources learned in its [`InputSourceState`]. Since the rules might depend
+/// on the title of the focused window, they are reconsidered whenever it
+/// changes.
+///
+/// For the apps configured to be handled per window, the input source of
+/// each window is remembered separately, falling back to the one of the app
+/// for the windows that have never been seen before.
+///
+/// Each [`Action::Select`] is expected to be echoed back as an
+/// [`Event::InputSourceChanged`], which might only arrive once another app is
+/// in focus. Such echoes are recognized and left unrecorded, so that the
+/// input source selected for one app is never recorded for another.
+///
+/// Backends that do not report every change (see
+/// [`InputSourceBackend::reports_every_change`]) might never echo a selection
+/// that has been reverted in the meantime, so [`Self::drive`] expects no
+/// echoes from them. Instead, a successful selection is taken as the current
+/// input source right away, and a change made right before the focus moves is
+/// attributed to the app that was in focus then.
+#[must_use]
+#[derive(Debug)]
+pub struct Engine {
+    state: InputSourceState,
+    config: Config,
+    prev_app: Option<String>,
+    /// The window of `prev_app` in focus, if the app is handled per window.
+    prev_window: Option<String>,
+    /// The title of the focused window of `prev_app`, if known.
+    prev_title: Option<String>,
+    prev_source: Option<String>,
+    /// The selections whose echoes have not been received yet, as pairs of
+    /// app and input source, the oldest first.
+    pending_echoes: VecDeque<(String, String)>,
+}
+
+impl Engine {
+    pub fn new(state: InputSourceState) -> Self {
+        Self {
+            state,
+            config: Config::default(),
+            prev_app: None,
+            prev_window: None,
+            prev_title: None,
+            prev_source: None,
+            pending_echoes: VecDeque::new(),
+        }
+    }
+
+    pub fn with_config(self, config: Config) -> Self {
+        Self { config, ..self }
+    }
+
+    pub const fn state(&self) -> &InputSourceState {
+        &self.state
+    }
+
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Replaces the engine's configuration, keeping its learned state.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Returns the bundle ID of the last activated app, if any.
+    #[must_use]
+    pub fn current_app(&self) -> Option<&str> {
+        self.prev_app.as_deref()
+    }
+
+    /// Consumes an [`Event`], returning the [`Action`]s to be carried out in
+    /// response.
+    #[must_use]
+    pub fn handle(&mut self, event: Event) -> Vec<Action> {
+        let actions = self.handle_inner(event);
+        for action in &actions {
+            if let Action::Select { app, source } = action {
+                self.pending_echoes.push_back((app.clone(), source.clone()));
+            }
+        }
+        actions
+    }
+
+    fn handle_inner(&mut self, event: Event) -> Vec<Action> {
+        match event {
+            Event::AppActivated {
+                app,
+                window,
+                title,
+                source,
+            } => {
+                let window = window.filter(|_| self.config.is_per_window(&app));
+                let prev_title = mem::replace(&mut self.prev_title, title.clone());
+                let pinned = self.config.pinned(&app, title.as_deref());
+                if self.prev_app.as_ref() == Some(&app)
+                    && self.prev_window == window
+                    && self.config.pinned(&app, prev_title.as_deref()) == pinned
+                {
+                    return vec![];
+                }
+                let pinned = pinned.map(ToOwned::to_owned);
+                self.prev_app = Some(app.clone());
+                self.prev_window.clone_from(&window);
+                if self.config.is_ignored(&app) {
+                    return vec![];
+                }
+                let remembered = (window.as_deref())
+                    .and_then(|window| self.state.load_window(&app, window))
+                    .or_else(|| self.state.load(&app));
+                if let (Some(window), Some(remembered)) = (window, &remembered) {
+                    // Remember the input source of a new window right away, so that it
+                    // is kept even if the one of the app changes in another window.
+                    self.state
+                        .save_window(app.clone(), window, remembered.clone());
+                }
+                match pinned.or(remembered) {
+                    Some(old_src) if old_src == source => vec![],
+                    Some(old_src) => vec![Action::Select {
+                        app,
+                        source: old_src,
+                    }],
+                    None => {
+                        let mut actions = vec![];
+                        let source = match &self.config.default {
+                            Some(default) if *default != source => {
+                                actions.push(Action::Select {
+                                    app: app.clone(),
+                                    source: default.clone(),
+                                });
+                                default.clone()
+                            }
+                            _ => source,
+                        };
+                        debug!("registering input source for `{app}` as `{source}`");
+                        actions.extend(self.record(app, source));
+                        actions
+                    }
+                }
+            }
+            Event::InputSourceChanged { app, source } => {
+                let echo = self.take_echo(&source);
+                if self.prev_source.as_ref() == Some(&source) {
+                    return vec![];
+                }
+                self.prev_source = Some(source.clone());
+                if let Some(selected_for) = echo {
+                    debug!("ignoring the echo of selecting `{source}` for `{selected_for}`");
+                    return vec![];
+                }
+                let title =
+                    (self.prev_title.as_deref()).filter(|_| self.prev_app.as_ref() == Some(&app));
+                if self.config.is_ignored(&app) || self.config.pinned(&app, title).is_some() {
+                    return vec![];
+                }
+                debug!("updating input source for `{app}` to `{source}`");
+                self.record(app, source)
+            }
+            Event::WindowClosed { app, window } => {
+                debug!("forgetting input source for window `{window}` of `{app}`");
+                _ = self.state.forget_window(&app, &window);
+                if self.prev_app.as_ref() == Some(&app) && self.prev_window == Some(window) {
+                    self.prev_window = None;
+                }
+                vec![]
+            }
+            Event::SelectionFailed { app, source } => {
+                // No echo is to be expected from a failed selection.
+                if let Some(i) = self.pending_echoes.iter().rposition(|(a, _)| *a == app) {
+                    self.pending_echoes.remove(i);
+                }
+                debug!("registering input source for `{app}` as `{source}`");
+                self.record(app, source)
+            }
+        }
+    }
+
+    /// Feeds `event` to the engine and carries out the resulting actions with
+    /// `backend`, returning all the actions that have been taken.
+    pub fn drive(
+        &mut self,
+        backend: &impl InputSourceBackend,
+        event: Event,
+    ) -> Result<Vec<Action>> {
+        let polled = !backend.reports_every_change();
+        let mut events = vec![event];
+        if polled
+            && let Event::AppActivated { source, .. } = &events[0]
+            && self.prev_source.as_ref() != Some(source)
+        {
+            match self.prev_app.clone() {
+                // The change has not been polled yet, but it has been made
+                // before the activation.
+                Some(app) => events.push(Event::InputSourceChanged {
+                    app,
+                    source: source.clone(),
+                }),
+                None => self.prev_source = Some(source.clone()),
+            }
+        }
+        let mut taken = vec![];
+        while let Some(event) = events.pop() {
+            let actions = if polled {
+                self.handle_inner(event)
+            } else {
+                self.handle(event)
+            };
+            for action in actions {
+                if let Action::Select { app, source } = &action {
+                    if !backend.select(source)? {
+                        events.push(Event::SelectionFailed {
+                            app: app.clone(),
+                            source: backend.current()?,
+                        });
+                    } else if polled {
+                        self.prev_source = Some(source.clone());
+                    }
+                }
+                taken.push(action);
+            }
+        }
+        Ok(taken)
+    }
+
+    /// Returns the app that `source` has been selected for if its change is
+    /// the echo of a pending selection.
+    ///
+    /// Echoes are received in the order of the selections, and might be
+    /// coalesced, so the pending selections older than the matching one are
+    /// considered echoed as well. A change that matches no pending selection
+    /// must have been made by the user after all of them.
+    fn take_echo(&mut self, source: &str) -> Option<String> {
+        let Some(i) = (self.pending_echoes.iter()).position(|(_, s)| s == source) else {
+            self.pending_echoes.clear();
+            return None;
+        };
+        let (app, _) = self.pending_echoes.drain(..=i).next_back()?;
+        Some(app)
+    }
+
+    /// Records `source` as the input source of `app`, as well as the one of
+    /// its window in focus if it is handled per window.
+    fn record(&self, app: String, source: String) -> Vec<Action> {
+        if let Some(window) = &self.prev_window
+            && self.prev_app.as_ref() == Some(&app)
+        {
+            (self.state).save_window(app.clone(), window.clone(), source.clone());
+        }
+        self.state.save(app.clone(), source.clone());
+        vec![Action::Record { app, source }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeBackend;
+
+    const ABC: &str = "com.apple.keylayout.ABC";
+    const PINYIN: &str = "com.apple.inputmethod.SCIM.ITABC";
+
+    fn activated(app: &str, source: &str) -> Event {
+        Event::AppActivated {
+            app: app.into(),
+            window: None,
+            title: None,
+            source: source.into(),
+        }
+    }
+
+    fn activated_in(app: &str, window: &str, source: &str) -> Event {
+        Event::AppActivated {
+            app: app.into(),
+            window: Some(window.into()),
+            title: None,
+            source: source.into(),
+        }
+    }
+
+    fn activated_titled(app: &str, title: &str, source: &str) -> Event {
+        Event::AppActivated {
+            app: app.into(),
+            window: None,
+            title: Some(title.into()),
+            source: source.into(),
+        }
+    }
+
+    fn changed(app: &str, source: &str) -> Event {
+        Event::InputSourceChanged {
+            app: app.into(),
+            source: source.into(),
+        }
+    }
+
+    fn select(app: &str, source: &str) -> Action {
+        Action::Select {
+            app: app.into(),
+            source: source.into(),
+        }
+    }
+
+    fn record(app: &str, source: &str) -> Action {
+        Action::Record {
+            app: app.into(),
+            source: source.into(),
+        }
+    }
+
+    fn run(events: impl IntoIterator<Item = Event>) -> (Engine, Vec<Vec<Action>>) {
+        let mut engine = Engine::new(InputSourceState::new());
+        let actions = events.into_iter().map(|e| engine.handle(e)).collect();
+        (engine, actions)
+    }
+
+    #[test]
+    fn test_register_unknown_app() {
+        let (engine, actions) = run([activated("foo", ABC)]);
+        assert_eq!(actions, [vec![record("foo", ABC)]]);
+        assert_eq!(engine.state().load("foo").as_deref(), Some(ABC));
+        assert_eq!(engine.current_app(), Some("foo"));
+    }
+
+    #[test]
+    fn test_restore_known_app() {
+        let (_, actions) = run([
+            activated("foo", ABC),
+            changed("foo", PINYIN),
+            activated("bar", PINYIN),
+            changed("bar", ABC),
+            activated("foo", ABC),
+        ]);
+        assert_eq!(
+            actions,
+            [
+                vec![record("foo", ABC)],
+                vec![record("foo", PINYIN)],
+                vec![record("bar", PINYIN)],
+                vec![record("bar", ABC)],
+                vec![select("foo", PINYIN)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_selecting_current_source() {
+        let (_, actions) = run([
+            activated("foo", ABC),
+            activated("bar", ABC),
+            activated("foo", ABC),
+        ]);
+        assert_eq!(actions[2], []);
+    }
+
+    #[test]
+    fn test_dedup_adjacent_events() {
+        let (_, actions) = run([
+            activated("foo", ABC),
+            activated("foo", PINYIN),
+            changed("foo", PINYIN),
+            changed("foo", PINYIN),
+        ]);
+        assert_eq!(
+            actions,
+            [
+                vec![record("foo", ABC)],
+                vec![],
+                vec![record("foo", PINYIN)],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_late_echoes() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), ABC.into());
+        let mut engine = Engine::new(state);
+        // A fast switch from `foo` to `bar`, with the echoes of both selections
+        // arriving once `bar` is in focus.
+        assert_eq!(
+            engine.handle(activated("foo", ABC)),
+            [select("foo", PINYIN)]
+        );
+        assert_eq!(
+            engine.handle(activated("bar", PINYIN)),
+            [select("bar", ABC)]
+        );
+        assert_eq!(engine.handle(changed("bar", PINYIN)), []);
+        assert_eq!(engine.handle(changed("bar", ABC)), []);
+        assert_eq!(engine.state().load("foo").as_deref(), Some(PINYIN));
+        assert_eq!(engine.state().load("bar").as_deref(), Some(ABC));
+
+        // Changes made by the user are recorded as usual.
+        assert_eq!(
+            engine.handle(changed("bar", PINYIN)),
+            [record("bar", PINYIN)]
+        );
+    }
+
+    #[test]
+    fn test_coalesced_echoes() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), ABC.into());
+        let mut engine = Engine::new(state);
+        _ = engine.handle(activated("foo", ABC));
+        _ = engine.handle(activated("bar", PINYIN));
+        // Only the echo of the last selection is received.
+        assert_eq!(engine.handle(changed("bar", ABC)), []);
+        // The earlier selection is no longer expected to be echoed.
+        assert_eq!(
+            engine.handle(changed("bar", PINYIN)),
+            [record("bar", PINYIN)]
+        );
+    }
+
+    #[test]
+    fn test_register_on_selection_failure() {
+        let state = InputSourceState::new();
+        state.save("foo".into(), "com.example.Missing".into());
+        let mut engine = Engine::new(state);
+        assert_eq!(
+            engine.handle(activated("foo", ABC)),
+            [select("foo", "com.example.Missing")]
+        );
+        let failed = Event::SelectionFailed {
+            app: "foo".into(),
+            source: ABC.into(),
+        };
+        assert_eq!(engine.handle(failed), [record("foo", ABC)]);
+        assert_eq!(engine.state().load("foo").as_deref(), Some(ABC));
+        // No echo is expected from the failed selection.
+        assert!(engine.pending_echoes.is_empty());
+    }
+
+    #[test]
+    fn test_drive() {
+        let backend = FakeBackend::with_ids([ABC, PINYIN]);
+        let state = InputSourceState::new();
+        state.save("foo".into(), PINYIN.into());
+        state.save("bar".into(), "com.example.Missing".into());
+        let mut engine = Engine::new(state);
+
+        let actions = engine.drive(&backend, activated("foo", ABC)).unwrap();
+        assert_eq!(actions, [select("foo", PINYIN)]);
+        assert_eq!(backend.current().unwrap(), PINYIN);
+
+        let actions = engine.drive(&backend, activated("bar", PINYIN)).unwrap();
+        assert_eq!(
+            actions,
+            [select("bar", "com.example.Missing"), record("bar", PINYIN)]
+        );
+        assert_eq!(backend.selections(), [PINYIN]);
+    }
+
+    #[test]
+    fn test_drive_polled() {
+        let backend = FakeBackend::with_ids([ABC, PINYIN]).with_polling();
+        let state = InputSourceState::new();
+        state.save("foo".into(), ABC.into());
+        state.save("bar".into(), PINYIN.into());
+        let mut engine = Engine::new(state);
+        assert_eq!(engine.drive(&backend, activated("foo", ABC)).unwrap(), []);
+
+        // A fast switch from `foo` to `bar` and back, which is never polled.
+        let actions = engine.drive(&backend, activated("bar", ABC)).unwrap();
+        assert_eq!(actions, [select("bar", PINYIN)]);
+        let actions = engine.drive(&backend, activated("foo", PINYIN)).unwrap();
+        assert_eq!(actions, [select("foo", ABC)]);
+        assert!(engine.pending_echoes.is_empty());
+
+        // The next change made by the user is not mistaken for an echo.
+        assert!(backend.switch(PINYIN));
+        assert_eq!(
+            engine.drive(&backend, changed("foo", PINYIN)).unwrap(),
+            [record("foo", PINYIN)]
+        );
+
+        // A change followed by an activation before it is polled is recorded
+        // for the app where it has been made.
+        assert!(backend.switch(ABC));
+        let actions = engine.drive(&backend, activated("bar", ABC)).unwrap();
+        assert_eq!(actions, [record("foo", ABC), select("bar", PINYIN)]);
+        assert_eq!(engine.drive(&backend, changed("bar", PINYIN)).unwrap(), []);
+        assert_eq!(engine.state().load("foo").as_deref(), Some(ABC));
+        assert_eq!(engine.state().load("bar").as_deref(), Some(PINYIN));
+    }
+
+    #[test]
+    fn test_per_window() {
+        let mut engine = Engine::new(InputSourceState::new()).with_config(Config {
+            per_window: vec!["term".parse().unwrap()],
+            ..Config::default()
+        });
+        assert_eq!(
+            engine.handle(activated_in("term", "1", ABC)),
+            [record("term", ABC)]
+        );
+        assert_eq!(
+            engine.handle(changed("term", PINYIN)),
+            [record("term", PINYIN)]
+        );
+        // A new window starts with the input source of the app.
+        assert_eq!(engine.handle(activated_in("term", "2", PINYIN)), []);
+        assert_eq!(engine.handle(changed("term", ABC)), [record("term", ABC)]);
+        assert_eq!(
+            engine.handle(activated_in("term", "1", ABC)),
+            [select("term", PINYIN)]
+        );
+        assert_eq!(
+            engine.handle(activated_in("term", "2", PINYIN)),
+            [select("term", ABC)]
+        );
+        assert_eq!(engine.state().window_count(), 2);
+
+        // Closed windows are forgotten, falling back to the app.
+        let closed = Event::WindowClosed {
+            app: "term".into(),
+            window: "1".into(),
+        };
+        assert_eq!(engine.handle(closed), []);
+        assert_eq!(engine.state().window_count(), 1);
+        assert_eq!(engine.state().load_window("term", "1"), None);
+
+        // Windows are irrelevant for the other apps.
+        assert_eq!(
+            engine.handle(activated_in("foo", "1", ABC)),
+            [record("foo", ABC)]
+        );
+        assert_eq!(engine.handle(activated_in("foo", "2", ABC)), []);
+        assert_eq!(engine.state().window_count(), 1);
+    }
+
+    #[test]
+    fn test_title_rules() {
+        let mut engine = Engine::new(InputSourceState::new()).with_config(
+            toml::from_str(&format!(
+                r#"
+                [[rules]]
+                app = "chrome"
+                title = "*Gmail*"
+                input-source = "{PINYIN}"
+                "#
+            ))
+            .unwrap(),
+        );
+        assert_eq!(
+            engine.handle(activated_titled("chrome", "GitHub", ABC)),
+            [record("chrome", ABC)]
+        );
+        // Switching to a tab matching the rule.
+        assert_eq!(
+            engine.handle(activated_titled("chrome", "Inbox - Gmail", ABC)),
+            [select("chrome", PINYIN)]
+        );
+        assert_eq!(engine.handle(changed("chrome", PINYIN)), []);
+        // Title changes that are irrelevant to the rules are ignored.
+        assert_eq!(
+            engine.handle(activated_titled("chrome", "Inbox (1) - Gmail", PINYIN)),
+            []
+        );
+        // Changes made while the rule applies are not recorded.
+        assert_eq!(engine.handle(changed("chrome", ABC)), []);
+        assert_eq!(engine.handle(changed("chrome", PINYIN)), []);
+        assert_eq!(engine.state().load("chrome").as_deref(), Some(ABC));
+
+        // Leaving the tab restores the input source of the app.
+        assert_eq!(
+            engine.handle(activated_titled("chrome", "GitHub", PINYIN)),
+            [select("chrome", ABC)]
+        );
+        assert_eq!(engine.handle(changed("chrome", ABC)), []);
+        assert_eq!(engine.handle(activated_titled("chrome", "Blog", ABC)), []);
+        assert_eq!(
+            engine.handle(changed("chrome", PINYIN)),
+            [record("chrome", PINYIN)]
+        );
+    }
+
+    fn config() -> Config {
+        toml::from_str(&format!(
+            r#"
+            default = "{ABC}"
+            ignore = ["baz"]
+
+            [[rules]]
+            app = "bar"
+            input-source = "{PINYIN}"
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_input_source() {
+        let mut engine = Engine::new(InputSourceState::new()).with_config(config());
+        assert_eq!(
+            engine.handle(activated("foo", PINYIN)),
+            [select("foo", ABC), record("foo", ABC)]
+        );
+    }
+
+    #[test]
+    fn test_pinned_input_source() {
+        let state = InputSourceState::new();
+        state.save("bar".into(), ABC.into());
+        let mut engine = Engine::new(state).with_config(config());
+        assert_eq!(
+            engine.handle(activated("bar", ABC)),
+            [select("bar", PINYIN)]
+        );
+        assert_eq!(engine.handle(changed("bar", ABC)), []);
+        assert_eq!(engine.state().load("bar").as_deref(), Some(ABC));
+    }
+
+    #[test]
+    fn test_ignored_app() {
+        let mut engine = Engine::new(InputSourceState::new()).with_config(config());
+        assert_eq!(engine.handle(activated("baz", PINYIN)), []);
+        assert_eq!(engine.handle(changed("baz", ABC)), []);
+        assert_eq!(engine.state().load("baz"), None);
+        assert_eq!(engine.current_app(), Some("baz"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::io;
 
+#[cfg(target_os = "macos")]
 use accessibility_sys::AXError;
 use thiserror::Error as ThisError;
 
@@ -13,11 +14,61 @@ pub enum Error {
     FaultyExePath,
     #[error("accessibility privileges are not detected")]
     AxPrivilegesNotDetected,
+    #[error("the daemon is not supported on this platform yet")]
+    UnsupportedPlatform,
+    #[error("another daemon is already running")]
+    DaemonAlreadyRunning,
+    #[error("the daemon has stopped")]
+    DaemonStopped,
+    #[error("the daemon replied with an error: {0}")]
+    Daemon(String),
+    #[error("no configuration file has been specified")]
+    NoConfigPath,
+    #[error("the daemon replied with an unexpected response")]
+    UnexpectedResponse,
+    #[error("`{0}` failed")]
+    CommandFailed(String),
+    #[error("no service is installed at `{0}`")]
+    ServiceNotInstalled(std::path::PathBuf),
+    #[error("the installed service has drifted from the current one")]
+    ServiceDrifted,
+    #[error("some checks have failed")]
+    ChecksFailed,
+    #[error("invalid app pattern `{0}`: {1}")]
+    InvalidPattern(String, #[source] regex::Error),
+    #[error("the state file has an unsupported schema version {0}")]
+    UnsupportedStateVersion(u32),
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("failed to parse the configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[cfg(target_os = "linux")]
+    #[error("neither $SWAYSOCK nor $I3SOCK is set")]
+    SwaySocketNotSet,
+    #[cfg(target_os = "linux")]
+    #[error("$HYPRLAND_INSTANCE_SIGNATURE is not set")]
+    HyprlandInstanceNotSet,
+    #[cfg(target_os = "linux")]
+    #[error("received a malformed reply from {0}")]
+    MalformedReply(&'static str),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    DBus(#[from] zbus::Error),
+    #[cfg(target_os = "linux")]
+    #[error("failed to connect to the X server: {0}")]
+    X11Connect(#[from] x11rb::errors::ConnectError),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    X11Connection(#[from] x11rb::errors::ConnectionError),
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    X11Reply(#[from] x11rb::errors::ReplyError),
 }
 
 // https://github.com/tasuren/window-observer-rs/blob/6981559652fdefe656926814f81464c5c23046d4/src/platform_impl/macos/helper.rs
+#[cfg(target_os = "macos")]
 #[derive(Clone, Copy, Debug, ThisError)]
 pub enum AccessibilityError {
     #[error("assistive applications are not enabled in System Preferences")]
@@ -58,6 +109,7 @@ pub enum AccessibilityError {
     ParameterizedAttributeUnsupported(i32),
 }
 
+#[cfg(target_os = "macos")]
 impl AccessibilityError {
     pub fn wrap(e: AXError) -> Result<(), Self> {
         match e.try_into() {
@@ -67,6 +119,7 @@ impl AccessibilityError {
     }
 }
 
+#[cfg(target_os = "macos")]
 impl TryFrom<AXError> for AccessibilityError {
     type Error = ();
 
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, subscription::Subscription};
+
+#[cfg(target_os = "macos")]
+pub mod appkit;
+#[cfg(target_os = "linux")]
+pub mod hyprland;
+pub mod scripted;
+#[cfg(target_os = "linux")]
+pub mod sway;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+/// The identifier of an app, e.g. its bundle ID on macOS.
+pub type AppId = String;
+
+/// The identifier of a window within its app, e.g. its container ID on sway.
+pub type WindowId = String;
+
+/// The reason why a [`FocusEvent`] has been emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusReason {
+    /// The app has been activated.
+    AppActivated,
+    /// The focused window has changed, e.g. when a popup window has been
+    /// opened or closed.
+    FocusedWindowChanged,
+    /// Another app has been hidden, leaving this one in focus.
+    AppHidden,
+    /// The title of the focused window of the app has changed, e.g. when
+    /// switching between browser tabs.
+    TitleChanged,
+    /// A window of the app has been closed.
+    ///
+    /// Unlike the other reasons, this does not mean that the app has gained
+    /// focus.
+    WindowClosed,
+}
+
+impl fmt::Display for FocusReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AppActivated => "app activation",
+            Self::FocusedWindowChanged => "focused window change",
+            Self::AppHidden => "app hiding",
+            Self::TitleChanged => "title change",
+            Self::WindowClosed => "window closing",
+        })
+    }
+}
+
+/// An app that has just gained focus, or a window that has just been closed
+/// as per [`FocusReason::WindowClosed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FocusEvent {
+    pub app: AppId,
+    pub reason: FocusReason,
+    /// The window concerned, if the focus source can tell windows apart.
+    pub window: Option<WindowId>,
+    /// The title of the focused window, if known.
+    pub title: Option<String>,
+    /// The raw platform event this event has been derived from, if known.
+    pub origin: Option<Origin>,
+}
+
+impl FocusEvent {
+    pub fn new(app: impl Into<AppId>, reason: FocusReason) -> Self {
+        Self {
+            app: app.into(),
+            reason,
+            window: None,
+            title: None,
+            origin: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_window(self, window: impl Into<WindowId>) -> Self {
+        Self {
+            window: Some(window.into()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_title(self, title: impl Into<String>) -> Self {
+        Self {
+            title: Some(title.into()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_origin(self, notification: impl Into<String>, pid: Option<u32>) -> Self {
+        Self {
+            origin: Some(Origin {
+                notification: notification.into(),
+                pid,
+            }),
+            ..self
+        }
+    }
+}
+
+/// The raw platform event behind a [`FocusEvent`], kept for diagnostics.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    /// The name of the notification, e.g.
+    /// `NSWorkspaceDidActivateApplicationNotification`.
+    pub notification: String,
+    /// The PID of the app the notification is about, if known.
+    pub pid: Option<u32>,
+}
+
+/// A platform-specific way of detecting app activations.
+pub trait FocusSource {
+    /// Returns the app that is currently in focus, if any.
+    fn current_app(&self) -> Option<AppId>;
+
+    /// Subscribes to the focus changes between apps.
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>>;
+}
+
+impl<F: FocusSource + ?Sized> FocusSource for Box<F> {
+    fn current_app(&self) -> Option<AppId> {
+        (**self).current_app()
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        (**self).subscribe()
+    }
+}
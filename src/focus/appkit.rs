@@ -0,0 +1,252 @@
+use libc::pid_t;
+use objc2::rc::Retained;
+use objc2_app_kit::{NSWorkspace, NSWorkspaceDidActivateApplicationNotification};
+use objc2_foundation::{NSNotification, NSNumber, NSString, ns_string};
+use smol::channel::{self, Sender};
+
+use super::{AppId, FocusEvent, FocusReason, FocusSource};
+use crate::{
+    error::Result,
+    observer::{
+        notification::{
+            APP_HIDDEN_NOTIFICATION, FOCUSED_WINDOW_CHANGED_NOTIFICATION,
+            LOCAL_NOTIFICATION_CENTER, NotificationObserver, TITLE_CHANGED_NOTIFICATION,
+            WINDOW_CLOSED_NOTIFICATION, WINDOW_NUMBER_KEY,
+        },
+        workspace::WorkspaceObserver,
+    },
+    pattern::AppPattern,
+    subscription::Subscription,
+    util::{
+        app_from_notification, bundle_id_from_current_app, bundle_id_from_pid,
+        focused_window_number, focused_window_title, pid_from_current_app,
+    },
+};
+
+/// The [`FocusSource`] backed by `NSWorkspace` and the Accessibility APIs.
+///
+/// Windows are identified by their window numbers.
+#[derive(Clone, Debug, Default)]
+pub struct AppKitFocusSource {
+    /// The bundle IDs to detect popup windows from.
+    pub detect_popup: Vec<AppPattern>,
+    /// The bundle IDs to track the titles of the focused windows from.
+    ///
+    /// Querying window titles involves the Accessibility APIs, so this is
+    /// limited to the apps that actually have rules depending on them.
+    pub watch_titles: Vec<AppPattern>,
+    /// The bundle IDs to tell the windows apart from.
+    ///
+    /// Like [`Self::watch_titles`], this is limited to the apps that are
+    /// actually handled per window.
+    pub per_window: Vec<AppPattern>,
+}
+
+impl AppKitFocusSource {
+    #[must_use]
+    pub fn new(detect_popup: impl IntoIterator<Item = AppPattern>) -> Self {
+        Self {
+            detect_popup: detect_popup.into_iter().collect(),
+            watch_titles: Vec::new(),
+            per_window: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_watch_titles(self, watch_titles: impl IntoIterator<Item = AppPattern>) -> Self {
+        Self {
+            watch_titles: watch_titles.into_iter().collect(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_per_window(self, per_window: impl IntoIterator<Item = AppPattern>) -> Self {
+        Self {
+            per_window: per_window.into_iter().collect(),
+            ..self
+        }
+    }
+}
+
+impl FocusSource for AppKitFocusSource {
+    fn current_app(&self) -> Option<AppId> {
+        bundle_id_from_current_app().map(|id| id.to_string())
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        let (tx, rx) = channel::unbounded();
+        let (watch_titles, per_window) = (self.watch_titles.clone(), self.per_window.clone());
+        // Returns the event for `bundle_id`, along with the number and the title
+        // of the focused window of the app with the given PID, if they are to be
+        // watched.
+        let event = move |bundle_id: &NSString, reason, pid: Option<pid_t>| {
+            let bundle_id = bundle_id.to_string();
+            let watched = |pats: &[AppPattern]| pats.iter().any(|pat| pat.matches(&bundle_id));
+            let window = pid
+                .filter(|_| watched(&per_window))
+                .and_then(focused_window_number);
+            let title = pid
+                .filter(|_| watched(&watch_titles))
+                .and_then(focused_window_title);
+            let mut event = FocusEvent::new(bundle_id, reason);
+            if let Some(window) = window {
+                event = event.with_window(window.to_string());
+            }
+            if let Some(title) = title {
+                event = event.with_title(title);
+            }
+            event
+        };
+        let send = |tx: &Sender<FocusEvent>,
+                    event: FocusEvent,
+                    notif: &NSNotification,
+                    pid: Option<pid_t>| {
+            let pid = pid.and_then(|pid| u32::try_from(pid).ok());
+            _ = tx.try_send(event.with_origin(notif.name().to_string(), pid));
+        };
+
+        let workspace_observer = WorkspaceObserver::new(
+            self.detect_popup.iter().cloned(),
+            self.watch_titles.iter().cloned(),
+            self.per_window.iter().cloned(),
+        );
+
+        let focused_window_observer = NotificationObserver::new(
+            LOCAL_NOTIFICATION_CENTER.clone(),
+            &NSString::from_str(FOCUSED_WINDOW_CHANGED_NOTIFICATION),
+            {
+                let (tx, event) = (tx.clone(), event.clone());
+                move |notif| {
+                    let notif = unsafe { notif.as_ref() };
+                    let Some(pid) = notif_pid(notif) else {
+                        return;
+                    };
+                    let Some(bundle_id) = bundle_id_from_pid(pid) else {
+                        return;
+                    };
+                    let event = event(&bundle_id, FocusReason::FocusedWindowChanged, Some(pid));
+                    send(&tx, event, notif, Some(pid));
+                }
+            },
+        );
+
+        let title_changed_observer = NotificationObserver::new(
+            LOCAL_NOTIFICATION_CENTER.clone(),
+            &NSString::from_str(TITLE_CHANGED_NOTIFICATION),
+            {
+                let (tx, event) = (tx.clone(), event.clone());
+                move |notif| {
+                    let notif = unsafe { notif.as_ref() };
+                    let Some(pid) = notif_pid(notif) else {
+                        return;
+                    };
+                    // This is also sent when the focused window of an app without
+                    // popup detection changes. Either way, the changes in the apps
+                    // in the background must not be mistaken for activations.
+                    if pid_from_current_app().ok() != Some(pid) {
+                        return;
+                    }
+                    let Some(bundle_id) = bundle_id_from_pid(pid) else {
+                        return;
+                    };
+                    let event = event(&bundle_id, FocusReason::TitleChanged, Some(pid));
+                    if event.window.is_none() && event.title.is_none() {
+                        return;
+                    }
+                    send(&tx, event, notif, Some(pid));
+                }
+            },
+        );
+
+        let app_hidden_observer = NotificationObserver::new(
+            LOCAL_NOTIFICATION_CENTER.clone(),
+            &NSString::from_str(APP_HIDDEN_NOTIFICATION),
+            {
+                let (tx, event) = (tx.clone(), event.clone());
+                move |notif| {
+                    let notif = unsafe { notif.as_ref() };
+                    let Some(bundle_id) = bundle_id_from_current_app() else {
+                        return;
+                    };
+                    let event = event(
+                        &bundle_id,
+                        FocusReason::AppHidden,
+                        pid_from_current_app().ok(),
+                    );
+                    // This is the PID of the app that has been hidden, not the one
+                    // in focus.
+                    send(&tx, event, notif, notif_pid(notif));
+                }
+            },
+        );
+
+        let window_closed_observer = NotificationObserver::new(
+            LOCAL_NOTIFICATION_CENTER.clone(),
+            &NSString::from_str(WINDOW_CLOSED_NOTIFICATION),
+            {
+                let tx = tx.clone();
+                move |notif| {
+                    let notif = unsafe { notif.as_ref() };
+                    let (Some(pid), Some(number)) = (notif_pid(notif), notif_window_number(notif))
+                    else {
+                        return;
+                    };
+                    let Some(bundle_id) = bundle_id_from_pid(pid) else {
+                        return;
+                    };
+                    let event = FocusEvent::new(bundle_id.to_string(), FocusReason::WindowClosed)
+                        .with_window(number.to_string());
+                    send(&tx, event, notif, Some(pid));
+                }
+            },
+        );
+
+        let did_activate_app_observer = unsafe {
+            NotificationObserver::new(
+                NSWorkspace::sharedWorkspace().notificationCenter(),
+                NSWorkspaceDidActivateApplicationNotification,
+                move |notif| {
+                    let Some(app) = app_from_notification(notif.as_ref()) else {
+                        return;
+                    };
+                    let Some(bundle_id) = app.bundleIdentifier() else {
+                        return;
+                    };
+                    let pid = app.processIdentifier();
+                    let event = event(&bundle_id, FocusReason::AppActivated, Some(pid));
+                    send(&tx, event, notif.as_ref(), Some(pid));
+                },
+            )
+        };
+
+        Ok(Subscription::new(
+            rx,
+            (
+                workspace_observer,
+                focused_window_observer,
+                title_changed_observer,
+                app_hidden_observer,
+                window_closed_observer,
+                did_activate_app_observer,
+            ),
+        ))
+    }
+}
+
+/// Returns the PID attached to a notification relayed through
+/// [`LOCAL_NOTIFICATION_CENTER`].
+fn notif_pid(notif: &NSNotification) -> Option<pid_t> {
+    let pid = notif.object()?;
+    Some(unsafe { Retained::cast_unchecked::<NSNumber>(pid) }.as_i32())
+}
+
+/// Returns the window number attached to a [`WINDOW_CLOSED_NOTIFICATION`].
+fn notif_window_number(notif: &NSNotification) -> Option<u32> {
+    let number = unsafe {
+        notif
+            .userInfo()?
+            .objectForKey(ns_string!(WINDOW_NUMBER_KEY))
+    }?;
+    Some(unsafe { Retained::cast_unchecked::<NSNumber>(number) }.as_u32())
+}
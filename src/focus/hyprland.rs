@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use smol::{
+    Timer, channel,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::unix::UnixStream,
+    stream::StreamExt,
+};
+use tracing::{info, warn};
+
+use super::{AppId, FocusEvent, FocusReason, FocusSource};
+use crate::{
+    error::{Error, Result},
+    subscription::Subscription,
+};
+
+/// How long to wait before trying to reconnect to the event socket again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How many times to try reconnecting to the event socket before giving up,
+/// since the Hyprland instance might have exited for good.
+const RECONNECT_ATTEMPTS: u32 = 30;
+
+/// The [`FocusSource`] backed by the event socket of Hyprland.
+///
+/// Apps are identified by their window class.
+#[derive(Clone, Debug)]
+pub struct HyprlandFocusSource {
+    /// The directory containing the sockets of the Hyprland instance.
+    socket_dir: PathBuf,
+}
+
+impl HyprlandFocusSource {
+    /// Connects to the Hyprland instance identified by
+    /// `$HYPRLAND_INSTANCE_SIGNATURE`.
+    pub fn new() -> Result<Self> {
+        let signature =
+            env::var_os("HYPRLAND_INSTANCE_SIGNATURE").ok_or(Error::HyprlandInstanceNotSet)?;
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR").map_or_else(
+            // Hyprland versions prior to v0.40 keep their sockets under `/tmp`.
+            || PathBuf::from("/tmp/hypr"),
+            |dir| PathBuf::from(dir).join("hypr"),
+        );
+        Ok(Self::with_socket_dir(runtime_dir.join(signature)))
+    }
+
+    #[must_use]
+    pub fn with_socket_dir(socket_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_dir: socket_dir.into(),
+        }
+    }
+
+    fn request_socket(&self) -> PathBuf {
+        self.socket_dir.join(".socket.sock")
+    }
+
+    fn event_socket(&self) -> PathBuf {
+        self.socket_dir.join(".socket2.sock")
+    }
+
+    async fn current_app_async(&self) -> Result<Option<AppId>> {
+        let mut stream = UnixStream::connect(self.request_socket()).await?;
+        stream.write_all(b"j/activewindow").await?;
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply).await?;
+        let window: ActiveWindow = serde_json::from_slice(&reply)?;
+        Ok(window.class.filter(|class| !class.is_empty()))
+    }
+}
+
+impl FocusSource for HyprlandFocusSource {
+    fn current_app(&self) -> Option<AppId> {
+        smol::block_on(self.current_app_async())
+            .inspect_err(|e| warn!("failed to query the active window: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        let path = self.event_socket();
+        let mut stream = smol::block_on(UnixStream::connect(&path))?;
+
+        let (tx, rx) = channel::unbounded();
+        let task = smol::spawn(async move {
+            let mut tracker = ActiveWindowTracker::default();
+            loop {
+                let mut lines = BufReader::new(stream).lines();
+                while let Some(line) = lines.next().await {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            warn!("failed to read from the Hyprland event socket: {e}");
+                            break;
+                        }
+                    };
+                    let Some(event) = tracker.feed(&line) else {
+                        continue;
+                    };
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                warn!("lost connection to the Hyprland event socket, reconnecting...");
+                let Some(new_stream) = reconnect(&path).await else {
+                    warn!("failed to reconnect to the Hyprland event socket, giving up");
+                    return;
+                };
+                stream = new_stream;
+                info!("reconnected to the Hyprland event socket");
+                // The windows might have changed in the meantime.
+                tracker = ActiveWindowTracker::default();
+            }
+        });
+        Ok(Subscription::new(rx, task))
+    }
+}
+
+/// Connects to the socket at `path`, making up to [`RECONNECT_ATTEMPTS`]
+/// attempts.
+async fn reconnect(path: &Path) -> Option<UnixStream> {
+    for _ in 0..RECONNECT_ATTEMPTS {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return Some(stream);
+        }
+        Timer::after(RECONNECT_DELAY).await;
+    }
+    None
+}
+
+/// Parses an `activewindow>>class,title` event line into the class and the
+/// title of the window.
+///
+/// Other events, as well as the events for focusing nothing at all, are
+/// skipped.
+fn parse_active_window(line: &str) -> Option<(&str, &str)> {
+    let (class, title) = line.strip_prefix("activewindow>>")?.split_once(',')?;
+    (!class.is_empty()).then_some((class, title))
+}
+
+/// Follows the active window through the event lines, turning them into
+/// [`FocusEvent`]s.
+///
+/// This is necessary since the class and the title of the active window are
+/// reported separately from its address, which is in turn the only thing
+/// reported along with title changes and closings.
+#[derive(Debug, Default)]
+struct ActiveWindowTracker {
+    /// The class and the title of the window reported by the last
+    /// `activewindow` event, waiting for its address.
+    active: Option<(String, String)>,
+    /// The address of the active window.
+    address: Option<String>,
+    /// The classes of the windows that have been active, by address.
+    classes: HashMap<String, String>,
+}
+
+impl ActiveWindowTracker {
+    fn feed(&mut self, line: &str) -> Option<FocusEvent> {
+        let (name, data) = line.split_once(">>")?;
+        match name {
+            "activewindow" => {
+                self.active =
+                    parse_active_window(line).map(|(class, title)| (class.into(), title.into()));
+                self.address = None;
+                None
+            }
+            "activewindowv2" => {
+                let (class, title) = self.active.take()?;
+                if data.is_empty() {
+                    return None;
+                }
+                self.address = Some(data.to_owned());
+                self.classes.insert(data.to_owned(), class.clone());
+                let event = FocusEvent::new(class, FocusReason::AppActivated)
+                    .with_window(data)
+                    .with_title(title)
+                    .with_origin("activewindowv2", None);
+                Some(event)
+            }
+            "windowtitlev2" => {
+                let (address, title) = data.split_once(',')?;
+                if self.address.as_deref() != Some(address) {
+                    return None;
+                }
+                let event = FocusEvent::new(self.classes.get(address)?, FocusReason::TitleChanged)
+                    .with_window(address)
+                    .with_title(title)
+                    .with_origin("windowtitlev2", None);
+                Some(event)
+            }
+            "closewindow" => {
+                let class = self.classes.remove(data)?;
+                if self.address.as_deref() == Some(data) {
+                    self.address = None;
+                }
+                let event = FocusEvent::new(class, FocusReason::WindowClosed)
+                    .with_window(data)
+                    .with_origin("closewindow", None);
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ActiveWindow {
+    class: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use smol::net::unix::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_active_window() {
+        assert_eq!(
+            parse_active_window("activewindow>>kitty,~/src, with a comma"),
+            Some(("kitty", "~/src, with a comma"))
+        );
+        assert_eq!(parse_active_window("activewindow>>,"), None);
+        assert_eq!(parse_active_window("activewindowv2>>564a0f2b5a40"), None);
+        assert_eq!(parse_active_window("workspace>>2"), None);
+    }
+
+    #[test]
+    fn test_active_window_tracker() {
+        let mut tracker = ActiveWindowTracker::default();
+        let mut feed = |line| {
+            (tracker.feed(line)).map(|e| {
+                let (window, title) = (e.window.unwrap(), e.title.unwrap_or_default());
+                (e.app, e.reason, window, title)
+            })
+        };
+        let event = |app: &str, reason, window: &str, title: &str| {
+            Some((app.into(), reason, window.into(), title.into()))
+        };
+        assert_eq!(feed("windowtitlev2>>564a0f2b5a40,fish"), None);
+        assert_eq!(feed("activewindow>>kitty,fish"), None);
+        assert_eq!(
+            feed("activewindowv2>>564a0f2b5a40"),
+            event("kitty", FocusReason::AppActivated, "564a0f2b5a40", "fish")
+        );
+        assert_eq!(
+            feed("windowtitlev2>>564a0f2b5a40,~/src"),
+            event("kitty", FocusReason::TitleChanged, "564a0f2b5a40", "~/src")
+        );
+        assert_eq!(feed("windowtitlev2>>564a0f2c0130,htop"), None);
+        assert_eq!(feed("activewindow>>kitty,htop"), None);
+        assert_eq!(
+            feed("activewindowv2>>564a0f2c0130"),
+            event("kitty", FocusReason::AppActivated, "564a0f2c0130", "htop")
+        );
+        assert_eq!(
+            feed("closewindow>>564a0f2b5a40"),
+            event("kitty", FocusReason::WindowClosed, "564a0f2b5a40", "")
+        );
+        assert_eq!(feed("closewindow>>564a0f2b5a40"), None);
+        assert_eq!(feed("activewindow>>,"), None);
+        assert_eq!(feed("activewindowv2>>"), None);
+        assert_eq!(feed("windowtitlev2>>564a0f2c0130,vim"), None);
+        assert_eq!(
+            feed("closewindow>>564a0f2c0130"),
+            event("kitty", FocusReason::WindowClosed, "564a0f2c0130", "")
+        );
+    }
+
+    #[test]
+    fn test_hyprland_focus_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let focus = HyprlandFocusSource::with_socket_dir(dir.path());
+        let events = UnixListener::bind(focus.event_socket()).unwrap();
+        let requests = UnixListener::bind(focus.request_socket()).unwrap();
+
+        // Feed the canned event lines over two connections in a row, so as to
+        // simulate a socket loss in between. The windows seen before are
+        // forgotten after reconnecting.
+        let server = smol::spawn(async move {
+            let chunks: [&[u8]; 2] = [
+                b"workspace>>2\nactivewindow>>kitty,fish\nactivewindowv2>>564a0f2b5a40\n\
+                windowtitlev2>>564a0f2b5a40,~/src\n",
+                b"activewindow>>,\nactivewindowv2>>\nactivewindow>>firefox,Mozilla Firefox\n\
+                activewindowv2>>564a0f2c0130\nclosewindow>>564a0f2b5a40\n\
+                closewindow>>564a0f2c0130\n",
+            ];
+            for chunk in chunks {
+                let (mut stream, _) = events.accept().await.unwrap();
+                stream.write_all(chunk).await.unwrap();
+            }
+            let (mut stream, _) = requests.accept().await.unwrap();
+            let mut req = [0; 14];
+            stream.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req, b"j/activewindow");
+            (stream.write_all(br#"{"class": "firefox", "title": "Mozilla Firefox"}"#))
+                .await
+                .unwrap();
+        });
+
+        let sub = focus.subscribe().unwrap();
+        let rx = sub.receiver();
+        let apps = smol::block_on(async {
+            let mut apps = vec![];
+            for _ in 0..4 {
+                let event = rx.recv().await.unwrap();
+                apps.push((event.app, event.reason, event.window.unwrap()));
+            }
+            apps
+        });
+        assert_eq!(
+            apps,
+            [
+                (
+                    "kitty".into(),
+                    FocusReason::AppActivated,
+                    "564a0f2b5a40".into()
+                ),
+                (
+                    "kitty".into(),
+                    FocusReason::TitleChanged,
+                    "564a0f2b5a40".into()
+                ),
+                (
+                    "firefox".into(),
+                    FocusReason::AppActivated,
+                    "564a0f2c0130".into()
+                ),
+                (
+                    "firefox".into(),
+                    FocusReason::WindowClosed,
+                    "564a0f2c0130".into()
+                ),
+            ]
+        );
+        assert_eq!(focus.current_app().as_deref(), Some("firefox"));
+        smol::block_on(server);
+    }
+}
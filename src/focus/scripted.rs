@@ -0,0 +1,40 @@
+use smol::channel::{self, Receiver};
+
+use super::{AppId, FocusEvent, FocusSource};
+use crate::{error::Result, subscription::Subscription};
+
+/// A [`FocusSource`] replaying a predefined list of [`FocusEvent`]s for
+/// testing purposes.
+///
+/// The events are delivered in order to a single subscriber, and the
+/// subscription is closed once all of them have been delivered.
+#[derive(Debug)]
+pub struct ScriptedFocusSource {
+    events: Vec<FocusEvent>,
+    rx: Receiver<FocusEvent>,
+}
+
+impl ScriptedFocusSource {
+    #[must_use]
+    pub fn new(events: impl IntoIterator<Item = FocusEvent>) -> Self {
+        let events: Vec<_> = events.into_iter().collect();
+        let (tx, rx) = channel::unbounded();
+        for event in &events {
+            tx.try_send(event.clone()).unwrap();
+        }
+        Self { events, rx }
+    }
+}
+
+impl FocusSource for ScriptedFocusSource {
+    /// Returns the app of the last event that has been received by the
+    /// subscriber.
+    fn current_app(&self) -> Option<AppId> {
+        let received = self.events.len() - self.rx.len();
+        Some(self.events.get(received.checked_sub(1)?)?.app.clone())
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        Ok(Subscription::new(self.rx.clone(), ()))
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use smol::{
+    Timer, channel,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::unix::UnixStream,
+};
+use tracing::{info, warn};
+
+use super::{AppId, FocusEvent, FocusReason, FocusSource};
+use crate::{
+    error::{Error, Result},
+    subscription::Subscription,
+};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const GET_TREE: u32 = 4;
+const SUBSCRIBE: u32 = 2;
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+/// How long to wait before trying to reconnect to the window manager again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How many times to try reconnecting to the window manager before giving
+/// up, since it might have exited for good.
+const RECONNECT_ATTEMPTS: u32 = 30;
+
+/// The [`FocusSource`] backed by the IPC interface of sway or i3.
+///
+/// Apps are identified by the `app_id` of native Wayland windows, or by the
+/// `WM_CLASS` class of X11 windows.
+#[derive(Clone, Debug)]
+pub struct SwayFocusSource {
+    socket_path: PathBuf,
+}
+
+impl SwayFocusSource {
+    /// Connects to the window manager listening at `$SWAYSOCK` or `$I3SOCK`.
+    pub fn new() -> Result<Self> {
+        let socket_path = (env::var_os("SWAYSOCK").or_else(|| env::var_os("I3SOCK")))
+            .ok_or(Error::SwaySocketNotSet)?;
+        Ok(Self::with_socket_path(socket_path))
+    }
+
+    #[must_use]
+    pub fn with_socket_path(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+        }
+    }
+
+    async fn current_app_async(&self) -> Result<Option<AppId>> {
+        let mut stream = UnixStream::connect(&self.socket_path).await?;
+        write_message(&mut stream, GET_TREE, b"").await?;
+        let (_, payload) = read_message(&mut stream).await?;
+        let tree: Node = serde_json::from_slice(&payload)?;
+        Ok(tree.focused().and_then(Node::app))
+    }
+}
+
+impl FocusSource for SwayFocusSource {
+    fn current_app(&self) -> Option<AppId> {
+        smol::block_on(self.current_app_async())
+            .inspect_err(|e| warn!("failed to query the focused window: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        let path = self.socket_path.clone();
+        let mut stream = smol::block_on(subscribe_window_events(&path))?;
+
+        let (tx, rx) = channel::unbounded();
+        let task = smol::spawn(async move {
+            loop {
+                let (ty, payload) = match read_message(&mut stream).await {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!("lost connection to the window manager ({e}), reconnecting...");
+                        let Some(new_stream) = reconnect(&path).await else {
+                            warn!("failed to reconnect to the window manager, giving up");
+                            break;
+                        };
+                        stream = new_stream;
+                        info!("reconnected to the window manager");
+                        continue;
+                    }
+                };
+                if ty != WINDOW_EVENT {
+                    continue;
+                }
+                let event = match serde_json::from_slice::<WindowEvent>(&payload) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("received malformed window event: {e}");
+                        continue;
+                    }
+                };
+                let reason = match &*event.change {
+                    "focus" => FocusReason::AppActivated,
+                    "close" => FocusReason::WindowClosed,
+                    // Title changes of the windows out of focus are irrelevant.
+                    "title" if event.container.focused => FocusReason::TitleChanged,
+                    _ => continue,
+                };
+                let Some(app) = event.container.app() else {
+                    continue;
+                };
+                let (window, title) = (event.container.id, event.container.name.clone());
+                let mut event = FocusEvent::new(app, reason)
+                    .with_origin(format!("window::{}", event.change), event.container.pid);
+                if let Some(window) = window {
+                    event = event.with_window(window.to_string());
+                }
+                if let Some(title) = title {
+                    event = event.with_title(title);
+                }
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Subscription::new(rx, task))
+    }
+}
+
+/// Connects to the IPC socket at `path`, subscribing to the window events.
+async fn subscribe_window_events(path: &Path) -> Result<UnixStream> {
+    let mut stream = UnixStream::connect(path).await?;
+    write_message(&mut stream, SUBSCRIBE, br#"["window"]"#).await?;
+    let (_, payload) = read_message(&mut stream).await?;
+    let reply: SubscribeReply = serde_json::from_slice(&payload)?;
+    if !reply.success {
+        return Err(Error::MalformedReply("sway"));
+    }
+    Ok(stream)
+}
+
+/// Subscribes to the window events again, making up to
+/// [`RECONNECT_ATTEMPTS`] attempts.
+async fn reconnect(path: &Path) -> Option<UnixStream> {
+    for _ in 0..RECONNECT_ATTEMPTS {
+        if let Ok(stream) = subscribe_window_events(path).await {
+            return Some(stream);
+        }
+        Timer::after(RECONNECT_DELAY).await;
+    }
+    None
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscribeReply {
+    success: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowEvent {
+    change: String,
+    container: Node,
+}
+
+/// A node of the layout tree.
+#[derive(Debug, Deserialize)]
+struct Node {
+    /// The unique ID of the node, which identifies windows as well.
+    id: Option<u64>,
+    /// The title of the node, if it is a window.
+    name: Option<String>,
+    #[serde(default)]
+    focused: bool,
+    app_id: Option<String>,
+    pid: Option<u32>,
+    window_properties: Option<WindowProperties>,
+    #[serde(default)]
+    nodes: Vec<Self>,
+    #[serde(default)]
+    floating_nodes: Vec<Self>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowProperties {
+    class: Option<String>,
+}
+
+impl Node {
+    fn app(&self) -> Option<AppId> {
+        (self.app_id.clone())
+            .or_else(|| self.window_properties.as_ref()?.class.clone())
+            .filter(|app| !app.is_empty())
+    }
+
+    /// Returns the focused node in the subtree rooted at this node, if any.
+    fn focused(&self) -> Option<&Self> {
+        if self.focused {
+            return Some(self);
+        }
+        (self.nodes.iter())
+            .chain(&self.floating_nodes)
+            .find_map(Self::focused)
+    }
+}
+
+/// Writes an IPC message, which is made of the magic string, the length of
+/// the payload and the message type as native-endian 32-bit integers, and the
+/// payload itself.
+async fn write_message(stream: &mut UnixStream, ty: u32, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| Error::MalformedReply("sway"))?;
+    let mut msg = MAGIC.to_vec();
+    msg.extend(len.to_ne_bytes());
+    msg.extend(ty.to_ne_bytes());
+    msg.extend(payload);
+    stream.write_all(&msg).await?;
+    Ok(())
+}
+
+/// Reads an IPC message, returning its type and payload.
+async fn read_message(stream: &mut UnixStream) -> Result<(u32, Vec<u8>)> {
+    let mut header = [0; MAGIC.len() + 8];
+    stream.read_exact(&mut header).await?;
+    let (magic, rest) = header.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(Error::MalformedReply("sway"));
+    }
+    let (len, ty) = rest.split_at(4);
+    let len = u32::from_ne_bytes(len.try_into().unwrap());
+    let ty = u32::from_ne_bytes(ty.try_into().unwrap());
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok((ty, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use smol::net::unix::UnixListener;
+
+    use super::*;
+
+    /// Window events as recorded from sway, trimmed down to the relevant
+    /// fields.
+    const EVENTS: [&str; 6] = [
+        r#"{"change": "focus", "container": {"id": 7, "name": "~", "focused": true, "app_id": "foot", "pid": 4242}}"#,
+        r#"{"change": "title", "container": {"id": 7, "name": "~/src", "focused": true, "app_id": "foot"}}"#,
+        r#"{"change": "title", "container": {"id": 8, "name": "htop", "focused": false, "app_id": "foot"}}"#,
+        r#"{"change": "focus", "container": {"focused": true, "app_id": null, "window_properties": {"class": "Slack"}}}"#,
+        r#"{"change": "focus", "container": {"focused": true, "app_id": "org.mozilla.firefox"}}"#,
+        r#"{"change": "close", "container": {"id": 7, "focused": false, "app_id": "foot"}}"#,
+    ];
+
+    const TREE: &str = r#"{
+        "focused": false,
+        "nodes": [{
+            "focused": false,
+            "nodes": [{"focused": false, "app_id": "foot"}],
+            "floating_nodes": [{"focused": true, "app_id": "pavucontrol"}]
+        }]
+    }"#;
+
+    /// Serves a single connection on `listener` like sway would, sending the
+    /// next chunk of `events` to subscribers.
+    async fn serve<'a>(listener: &UnixListener, events: &mut impl Iterator<Item = &'a [&'a str]>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (ty, payload) = read_message(&mut stream).await.unwrap();
+        match ty {
+            GET_TREE => write_message(&mut stream, GET_TREE, TREE.as_bytes())
+                .await
+                .unwrap(),
+            SUBSCRIBE => {
+                assert_eq!(payload, br#"["window"]"#);
+                write_message(&mut stream, SUBSCRIBE, br#"{"success": true}"#)
+                    .await
+                    .unwrap();
+                for event in events.next().unwrap() {
+                    write_message(&mut stream, WINDOW_EVENT, event.as_bytes())
+                        .await
+                        .unwrap();
+                }
+            }
+            _ => panic!("unexpected message type {ty}"),
+        }
+    }
+
+    #[test]
+    fn test_sway_focus_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("sway-ipc.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        // Send the events over two subscriptions in a row, so as to simulate
+        // a socket loss in between.
+        let server = smol::spawn(async move {
+            let mut events = [&EVENTS[..3], &EVENTS[3..]].into_iter();
+            for _ in 0..3 {
+                serve(&listener, &mut events).await;
+            }
+        });
+
+        let focus = SwayFocusSource::with_socket_path(socket_path);
+        assert_eq!(focus.current_app().as_deref(), Some("pavucontrol"));
+        let sub = focus.subscribe().unwrap();
+        smol::block_on(server);
+
+        let rx = sub.receiver();
+        let events: Vec<_> = smol::block_on(async {
+            let mut events = vec![];
+            for _ in 0..5 {
+                events.push(rx.recv().await.unwrap());
+            }
+            events
+        });
+        let apps: Vec<_> = events.iter().map(|e| e.app.as_str()).collect();
+        assert_eq!(
+            apps,
+            ["foot", "foot", "Slack", "org.mozilla.firefox", "foot"]
+        );
+        assert_eq!(events[0].origin.as_ref().unwrap().pid, Some(4242));
+        assert_eq!(events[0].window.as_deref(), Some("7"));
+        assert_eq!(events[0].title.as_deref(), Some("~"));
+        assert_eq!(events[1].reason, FocusReason::TitleChanged);
+        assert_eq!(events[1].title.as_deref(), Some("~/src"));
+        assert_eq!(events[4].reason, FocusReason::WindowClosed);
+        assert_eq!(events[4].window.as_deref(), Some("7"));
+    }
+}
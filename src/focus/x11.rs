@@ -0,0 +1,338 @@
+use std::{collections::HashMap, os::fd::AsFd};
+
+use smol::{Async, channel};
+use tracing::{debug, warn};
+use x11rb::{
+    NONE,
+    connection::Connection,
+    protocol::{
+        Event,
+        xproto::{Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+use super::{AppId, FocusEvent, FocusReason, FocusSource};
+use crate::{error::Result, subscription::Subscription};
+
+/// The [`FocusSource`] backed by the `_NET_ACTIVE_WINDOW` property of the X11
+/// root window, as maintained by EWMH-compliant window managers.
+///
+/// Apps are identified by the class part of the `WM_CLASS` property of
+/// their windows, e.g. `Firefox`.
+#[derive(Clone, Debug, Default)]
+pub struct X11FocusSource {
+    /// The display to connect to, defaulting to `$DISPLAY`.
+    display: Option<String>,
+}
+
+impl X11FocusSource {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_display(display: impl Into<String>) -> Self {
+        Self {
+            display: Some(display.into()),
+        }
+    }
+}
+
+impl FocusSource for X11FocusSource {
+    fn current_app(&self) -> Option<AppId> {
+        X11Client::connect(self.display.as_deref())
+            .and_then(|client| client.active_app())
+            .inspect_err(|e| warn!("failed to query the active window: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    fn subscribe(&self) -> Result<Subscription<FocusEvent>> {
+        let client = X11Client::connect(self.display.as_deref())?;
+        let aux = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+        (client.conn)
+            .change_window_attributes(client.root, &aux)?
+            .check()?;
+        // The connection's stream is non-blocking already.
+        let readiness = Async::new_nonblocking(client.conn.stream().as_fd().try_clone_to_owned()?)?;
+
+        let (tx, rx) = channel::unbounded();
+        let task = smol::spawn(async move {
+            // The apps owning the windows that have been active, followed until
+            // they are destroyed.
+            let mut windows = HashMap::<Window, AppId>::new();
+            loop {
+                loop {
+                    let event = match client.conn.poll_for_event() {
+                        Ok(Some(event)) => event,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("lost connection to the X server: {e}");
+                            return;
+                        }
+                    };
+                    let event = match event {
+                        Event::PropertyNotify(event)
+                            if event.window == client.root
+                                && event.atom == client.net_active_window =>
+                        {
+                            let (window, app) = match client.active_window() {
+                                Ok(Some(active)) => active,
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    // The window might have been destroyed in the meantime.
+                                    debug!("failed to query the active window: {e}");
+                                    continue;
+                                }
+                            };
+                            if !windows.contains_key(&window) {
+                                _ = client.follow(window).inspect_err(|e| {
+                                    debug!("failed to follow window {window}: {e}");
+                                });
+                            }
+                            windows.insert(window, app.clone());
+                            FocusEvent::new(app, FocusReason::AppActivated)
+                                .with_window(window.to_string())
+                                .with_origin("_NET_ACTIVE_WINDOW", None)
+                        }
+                        Event::DestroyNotify(event) => {
+                            let Some(app) = windows.remove(&event.window) else {
+                                continue;
+                            };
+                            FocusEvent::new(app, FocusReason::WindowClosed)
+                                .with_window(event.window.to_string())
+                                .with_origin("DestroyNotify", None)
+                        }
+                        _ => continue,
+                    };
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                if readiness.readable().await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Subscription::new(rx, task))
+    }
+}
+
+/// A connection to the X server.
+struct X11Client {
+    conn: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+}
+
+impl X11Client {
+    fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen) = RustConnection::connect(display)?;
+        let root = conn.setup().roots[screen].root;
+        let net_active_window = conn
+            .intern_atom(false, b"_NET_ACTIVE_WINDOW")?
+            .reply()?
+            .atom;
+        Ok(Self {
+            conn,
+            root,
+            net_active_window,
+        })
+    }
+
+    /// Returns the active window along with the app owning it, if any.
+    fn active_window(&self) -> Result<Option<(Window, AppId)>> {
+        let reply = (self.conn)
+            .get_property(
+                false,
+                self.root,
+                self.net_active_window,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        let Some(window) = reply.value32().and_then(|mut it| it.next()) else {
+            return Ok(None);
+        };
+        if window == NONE {
+            return Ok(None);
+        }
+        let reply = (self.conn)
+            .get_property(
+                false,
+                window,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        Ok(wm_class(&reply.value).map(|app| (window, app)))
+    }
+
+    /// Returns the app owning the active window, if any.
+    fn active_app(&self) -> Result<Option<AppId>> {
+        Ok(self.active_window()?.map(|(_, app)| app))
+    }
+
+    /// Subscribes to the structural changes of `window`, including its
+    /// destruction.
+    fn follow(&self, window: Window) -> Result<()> {
+        let aux = ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY);
+        (self.conn)
+            .change_window_attributes(window, &aux)?
+            .ignore_error();
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// Extracts the class from the value of a `WM_CLASS` property, which is made
+/// of two null-terminated strings: the instance name and the class name.
+fn wm_class(value: &[u8]) -> Option<AppId> {
+    let mut parts = value.split(|&b| b == 0);
+    let _instance = parts.next()?;
+    let class = parts.next().filter(|class| !class.is_empty())?;
+    Some(String::from_utf8_lossy(class).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use smol::{Timer, future};
+    use x11rb::{
+        COPY_DEPTH_FROM_PARENT,
+        protocol::xproto::{CreateWindowAux, PropMode, WindowClass},
+        wrapper::ConnectionExt as _,
+    };
+
+    use super::*;
+
+    /// A headless X server, shut down when dropped.
+    struct Xvfb {
+        server: Child,
+        display: String,
+    }
+
+    impl Xvfb {
+        /// Starts a new `Xvfb`, which must be available in `$PATH`.
+        fn start() -> Self {
+            let mut server = Command::new("Xvfb")
+                .args(["-displayfd", "1", "-nolisten", "tcp"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("failed to start `Xvfb`");
+            let mut display = String::new();
+            BufReader::new(server.stdout.take().unwrap())
+                .read_line(&mut display)
+                .unwrap();
+            Self {
+                server,
+                display: format!(":{}", display.trim()),
+            }
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            _ = self.server.kill();
+            _ = self.server.wait();
+        }
+    }
+
+    #[test]
+    fn test_wm_class() {
+        assert_eq!(
+            wm_class(b"Navigator\0Firefox\0").as_deref(),
+            Some("Firefox")
+        );
+        assert_eq!(wm_class(b"Navigator\0\0"), None);
+        assert_eq!(wm_class(b""), None);
+    }
+
+    #[test]
+    #[ignore = "requires `Xvfb`"]
+    fn test_x11_focus_source() {
+        let xvfb = Xvfb::start();
+        let focus = X11FocusSource::with_display(&xvfb.display);
+        assert_eq!(focus.current_app(), None);
+
+        // Play the part of both the client windows and the window manager.
+        let client = X11Client::connect(Some(&xvfb.display)).unwrap();
+        let conn = &client.conn;
+        let windows = [b"xterm\0XTerm\0".as_slice(), b"Navigator\0Firefox\0"].map(|class| {
+            let window = conn.generate_id().unwrap();
+            (conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                client.root,
+                0,
+                0,
+                100,
+                100,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                0,
+                &CreateWindowAux::new(),
+            ))
+            .unwrap();
+            (conn.change_property8(
+                PropMode::REPLACE,
+                window,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                class,
+            ))
+            .unwrap();
+            window
+        });
+        conn.flush().unwrap();
+
+        let sub = focus.subscribe().unwrap();
+        let rx = sub.receiver();
+        let recv = || {
+            let event = smol::block_on(future::or(async { rx.recv().await.ok() }, async {
+                Timer::after(Duration::from_secs(5)).await;
+                None
+            }));
+            let event = event.unwrap();
+            (event.app, event.reason, event.window.unwrap())
+        };
+        let mut events = vec![];
+        for window in windows {
+            (conn.change_property32(
+                PropMode::REPLACE,
+                client.root,
+                client.net_active_window,
+                AtomEnum::WINDOW,
+                &[window],
+            ))
+            .unwrap();
+            conn.flush().unwrap();
+            events.push(recv());
+        }
+        conn.destroy_window(windows[0]).unwrap();
+        conn.flush().unwrap();
+        events.push(recv());
+
+        let [xterm, firefox] = windows.map(|window| window.to_string());
+        assert_eq!(
+            events,
+            [
+                ("XTerm".into(), FocusReason::AppActivated, xterm.clone()),
+                ("Firefox".into(), FocusReason::AppActivated, firefox),
+                ("XTerm".into(), FocusReason::WindowClosed, xterm),
+            ]
+        );
+        assert_eq!(focus.current_app().as_deref(), Some("Firefox"));
+    }
+}
@@ -0,0 +1,171 @@
+//! The local control socket of the daemon.
+//!
+//! The protocol is line-delimited JSON: each line sent by a client is a
+//! [`Request`], to which the daemon replies with a single line containing a
+//! [`Response`].
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use smol::{
+    channel::{self, Sender},
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    net::unix::UnixListener,
+    stream::StreamExt,
+};
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+
+/// A request sent to the daemon.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Queries the current state of the daemon.
+    Status,
+    /// Lists the input sources remembered for each app.
+    ListMappings,
+    /// Remembers `input_source` as the input source of `app`.
+    SetMapping { app: String, input_source: String },
+    /// Forgets the input source remembered for `app`.
+    ForgetMapping { app: String },
+    /// Forgets all the remembered input sources.
+    ClearMappings,
+    /// Reloads the configuration file.
+    ReloadConfig,
+    /// Asks the daemon to exit.
+    Shutdown,
+}
+
+/// A response sent back by the daemon.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Response {
+    /// The request has been carried out successfully.
+    Done,
+    /// The current state of the daemon.
+    Status {
+        pid: u32,
+        started_at: u64,
+        current_app: Option<String>,
+        input_source: Option<String>,
+        mappings: usize,
+    },
+    /// The input sources remembered for each app.
+    Mappings { mappings: BTreeMap<String, String> },
+    /// The request has failed.
+    Error { message: String },
+}
+
+/// A [`Request`] to be handled by the daemon, along with the channel to send
+/// the corresponding [`Response`] to.
+pub type Call = (Request, Sender<Response>);
+
+/// Binds the control socket to `path`.
+///
+/// Any stale socket left at `path` by a daemon that is no longer running is
+/// removed beforehand.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::DaemonAlreadyRunning);
+        }
+        fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Accepts connections from `listener` indefinitely, forwarding each
+/// incoming [`Request`] to `calls`.
+pub async fn serve(listener: UnixListener, calls: Sender<Call>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let calls = calls.clone();
+        smol::spawn(async move {
+            if let Err(e) = handle_connection(stream, calls).await {
+                debug!("control connection closed with error: {e}");
+            }
+        })
+        .detach();
+    }
+}
+
+async fn handle_connection(stream: smol::net::unix::UnixStream, calls: Sender<Call>) -> Result<()> {
+    let mut writer = stream.clone();
+    let mut lines = smol::io::BufReader::new(stream).lines();
+    while let Some(line) = lines.next().await {
+        let resp = match serde_json::from_str::<Request>(&line?) {
+            Ok(req) => {
+                let (tx, rx) = channel::bounded(1);
+                calls
+                    .send((req, tx))
+                    .await
+                    .map_err(|_| Error::DaemonStopped)?;
+                rx.recv().await.map_err(|_| Error::DaemonStopped)?
+            }
+            Err(e) => {
+                warn!("received malformed request: {e}");
+                Response::Error {
+                    message: format!("malformed request: {e}"),
+                }
+            }
+        };
+        let mut resp = serde_json::to_string(&resp)?;
+        resp.push('\n');
+        writer.write_all(resp.as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+/// A blocking client of the control socket.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Sends `req` to the daemon and waits for its [`Response`].
+    ///
+    /// [`Response::Error`]s are turned into [`Error::Daemon`].
+    ///
+    /// # Note
+    /// Since the daemon might exit before replying to a [`Request::Shutdown`],
+    /// the connection being closed is considered a successful reply to it.
+    pub fn call(&mut self, req: &Request) -> Result<Response> {
+        let mut line = serde_json::to_string(req)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+
+        line.clear();
+        if self.reader.read_line(&mut line)? == 0 {
+            return match req {
+                Request::Shutdown => Ok(Response::Done),
+                _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            };
+        }
+        match serde_json::from_str(&line)? {
+            Response::Error { message } => Err(Error::Daemon(message)),
+            resp => Ok(resp),
+        }
+    }
+}
@@ -1,4 +1,18 @@
+pub mod backend;
+pub mod config;
+pub mod daemon;
+pub mod debounce;
+pub mod doctor;
+pub mod engine;
 pub mod error;
+pub mod focus;
+pub mod ipc;
+#[cfg(target_os = "macos")]
 pub mod observer;
+pub mod pattern;
+pub mod recording;
 pub mod service;
+pub mod state;
+pub mod status;
+pub mod subscription;
 pub mod util;
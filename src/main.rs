@@ -1,6 +1,5 @@
 use clap::Parser;
 use clavy::error::Result;
-use embed_plist::embed_info_plist;
 
 use crate::cmd::Clavy;
 
@@ -10,7 +9,8 @@ mod _built {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
-embed_info_plist!("../assets/Info.plist");
+#[cfg(target_os = "macos")]
+embed_plist::embed_info_plist!("../assets/Info.plist");
 
 fn main() -> Result<()> {
     Clavy::parse().dispatch()
@@ -1,4 +1,3 @@
-pub mod input_source;
 pub mod notification;
 pub mod window;
 pub mod workspace;
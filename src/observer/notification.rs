@@ -10,6 +10,12 @@ pub static LOCAL_NOTIFICATION_CENTER: LazyLock<Retained<NSNotificationCenter>> =
 
 pub const FOCUSED_WINDOW_CHANGED_NOTIFICATION: &str = "ClavyFocusedWindowsChangedNotification";
 pub const APP_HIDDEN_NOTIFICATION: &str = "ClavyAppHiddenNotification";
+pub const TITLE_CHANGED_NOTIFICATION: &str = "ClavyTitleChangedNotification";
+pub const WINDOW_CLOSED_NOTIFICATION: &str = "ClavyWindowClosedNotification";
+
+/// The key of the window number in the `userInfo` of
+/// [`WINDOW_CLOSED_NOTIFICATION`].
+pub const WINDOW_NUMBER_KEY: &str = "ClavyWindowNumber";
 
 #[derive(Debug)]
 pub struct NotificationObserver {
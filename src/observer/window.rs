@@ -2,6 +2,7 @@
 
 use std::{
     borrow::Cow,
+    cell::RefCell,
     ffi::c_void,
     fmt,
     pin::Pin,
@@ -11,18 +12,23 @@ use std::{
 use accessibility_sys::{
     AXObserverAddNotification, AXObserverCreate, AXObserverGetRunLoopSource, AXObserverRef,
     AXObserverRemoveNotification, AXUIElementCreateApplication, AXUIElementRef,
+    kAXUIElementDestroyedNotification, kAXWindowsAttribute,
 };
 use core_foundation::{
-    base::{CFRelease, TCFType, ToVoid},
+    array::CFArray,
+    base::{CFRelease, CFType, TCFType, ToVoid},
     runloop,
     string::{CFString, CFStringRef},
 };
 use libc::pid_t;
 use tracing::debug;
 
-use crate::error::AccessibilityError;
+use crate::{
+    error::AccessibilityError,
+    util::{ax_ui_element_value, window_number},
+};
 
-pub type OnNotifFn = Box<dyn Fn(&WindowObserver, Cow<'_, str>)>;
+pub type OnNotifFn = Box<dyn Fn(&WindowObserver, Cow<'_, str>, AXUIElementRef)>;
 
 // Special thanks to
 // <https://stackoverflow.com/questions/36264038/cocoa-programmatically-detect-frontmost-floating-windows>
@@ -34,6 +40,9 @@ pub struct WindowObserver {
     pid: pid_t,
     elem: AXUIElementRef,
     raw: AXObserverRef,
+    /// The windows watched for their destruction, along with their window
+    /// numbers, which can no longer be queried once they are gone.
+    windows: RefCell<Vec<(CFType, u32)>>,
     pub on_notif: OnNotifFn,
 }
 
@@ -43,6 +52,7 @@ impl fmt::Debug for WindowObserver {
             .field("pid", &self.pid)
             .field("elem", &self.elem)
             .field("raw", &self.raw)
+            .field("windows", &self.windows)
             .field("on_notif", &"<fn>")
             .finish()
     }
@@ -63,7 +73,7 @@ impl WindowObserver {
     pub fn try_new(pid: pid_t, on_notif: OnNotifFn) -> Result<Pin<Box<Self>>, AccessibilityError> {
         unsafe extern "C" fn callback(
             _: AXObserverRef,
-            _: AXUIElementRef,
+            elem: AXUIElementRef,
             notif: CFStringRef,
             refcon: *mut c_void,
         ) {
@@ -74,7 +84,7 @@ impl WindowObserver {
             let pid = self_.pid();
             let notif = unsafe { CFString::wrap_under_get_rule(notif) };
             debug!("received `{notif}` from PID {pid}");
-            (self_.on_notif)(self_, Cow::from(&notif));
+            (self_.on_notif)(self_, Cow::from(&notif), elem);
         }
 
         let mut raw = ptr::null_mut();
@@ -85,6 +95,7 @@ impl WindowObserver {
             pid,
             on_notif,
             raw,
+            windows: RefCell::default(),
             elem: unsafe { AXUIElementCreateApplication(pid) },
         }))
     }
@@ -106,6 +117,48 @@ impl WindowObserver {
         })
     }
 
+    /// Subscribes to the destruction of `window`.
+    pub fn watch_window(&self, window: AXUIElementRef) -> Result<(), AccessibilityError> {
+        let number = window_number(window)?;
+        let window = unsafe { CFType::wrap_under_get_rule(window.cast()) };
+        if self.windows.borrow().iter().any(|(it, _)| *it == window) {
+            return Ok(());
+        }
+        AccessibilityError::wrap(unsafe {
+            AXObserverAddNotification(
+                self.raw,
+                window.as_CFTypeRef() as AXUIElementRef,
+                CFString::new(kAXUIElementDestroyedNotification)
+                    .to_void()
+                    .cast(),
+                ptr::from_ref(self).cast_mut().cast(),
+            )
+        })?;
+        self.windows.borrow_mut().push((window, number));
+        Ok(())
+    }
+
+    /// Subscribes to the destruction of the windows currently open.
+    pub fn watch_windows(&self) -> Result<(), AccessibilityError> {
+        let windows = ax_ui_element_value(self.elem, kAXWindowsAttribute)?;
+        let windows = unsafe { CFArray::<CFType>::wrap_under_create_rule(windows.cast()) };
+        for window in &windows {
+            _ = (self.watch_window(window.as_CFTypeRef() as AXUIElementRef)).inspect_err(|e| {
+                debug!("failed to watch window of PID {}: {e}", self.pid);
+            });
+        }
+        Ok(())
+    }
+
+    /// Stops watching `window` after its destruction, returning its window
+    /// number if it has been watched.
+    pub fn forget_window(&self, window: AXUIElementRef) -> Option<u32> {
+        let window = unsafe { CFType::wrap_under_get_rule(window.cast()) };
+        let mut windows = self.windows.borrow_mut();
+        let i = windows.iter().position(|(it, _)| *it == window)?;
+        Some(windows.swap_remove(i).1)
+    }
+
     pub fn start(&mut self) {
         unsafe {
             runloop::CFRunLoopAddSource(
@@ -6,7 +6,10 @@ use std::{
     sync::{Mutex, OnceLock},
 };
 
-use accessibility_sys::{kAXApplicationHiddenNotification, kAXFocusedWindowChangedNotification};
+use accessibility_sys::{
+    kAXApplicationHiddenNotification, kAXFocusedWindowChangedNotification,
+    kAXTitleChangedNotification, kAXUIElementDestroyedNotification, kAXWindowCreatedNotification,
+};
 use core_foundation::{base::FromVoid, dictionary::CFDictionary, number::CFNumber};
 use core_graphics::window::{
     copy_window_info, kCGNullWindowID, kCGWindowListOptionAll, kCGWindowOwnerPID,
@@ -25,15 +28,21 @@ use objc2_foundation::{
 use tracing::{debug, trace, warn};
 
 use super::window::WindowObserver;
-use crate::observer::notification::{
-    APP_HIDDEN_NOTIFICATION, FOCUSED_WINDOW_CHANGED_NOTIFICATION, LOCAL_NOTIFICATION_CENTER,
+use crate::{
+    observer::notification::{
+        APP_HIDDEN_NOTIFICATION, FOCUSED_WINDOW_CHANGED_NOTIFICATION, LOCAL_NOTIFICATION_CENTER,
+        TITLE_CHANGED_NOTIFICATION, WINDOW_CLOSED_NOTIFICATION, WINDOW_NUMBER_KEY,
+    },
+    pattern::AppPattern,
 };
 
 #[derive(Debug)]
 pub struct WorkspaceObserverIvars {
     workspace: Retained<NSWorkspace>,
     children: Mutex<HashMap<pid_t, Pin<Box<WindowObserver>>>>,
-    allowed_app_ids: OnceLock<HashSet<String>>,
+    allowed_app_ids: OnceLock<Vec<AppPattern>>,
+    title_app_ids: OnceLock<Vec<AppPattern>>,
+    per_window_app_ids: OnceLock<Vec<AppPattern>>,
 }
 
 define_class![
@@ -49,6 +58,8 @@ define_class![
                 workspace: NSWorkspace::sharedWorkspace(),
                 children: Mutex::default(),
                 allowed_app_ids: OnceLock::default(),
+                title_app_ids: OnceLock::default(),
+                per_window_app_ids: OnceLock::default(),
             });
             unsafe { msg_send![super(this), init] }
         }
@@ -96,20 +107,29 @@ impl WorkspaceObserver {
         "com.contextsformac.Contexts",
     ];
 
+    /// Creates an observer detecting popup windows from the apps matching
+    /// `allowed_app_ids`, window title changes from the ones matching
+    /// `title_app_ids`, and focused window changes and window closings from
+    /// the ones matching `per_window_app_ids`.
     #[must_use]
-    pub fn new<S: AsRef<str>>(allowed_app_ids: impl IntoIterator<Item = S>) -> Retained<Self> {
+    pub fn new(
+        allowed_app_ids: impl IntoIterator<Item = AppPattern>,
+        title_app_ids: impl IntoIterator<Item = AppPattern>,
+        per_window_app_ids: impl IntoIterator<Item = AppPattern>,
+    ) -> Retained<Self> {
         let res: Retained<Self> = unsafe { msg_send![Self::alloc(), init] };
-        let mut allowed_app_ids: HashSet<_> = allowed_app_ids
+        let allowed_app_ids = Self::KNOWN_POPUP_ONLY_APP_IDS
             .into_iter()
-            .map(|s| s.as_ref().to_owned())
+            .map(|id| id.parse().unwrap())
+            .chain(allowed_app_ids)
             .collect();
-        for id in Self::KNOWN_POPUP_ONLY_APP_IDS {
-            allowed_app_ids.insert(id.to_owned());
-        }
-        for id in Self::EXCLUDED_APP_IDS {
-            allowed_app_ids.remove(id);
-        }
         res.ivars().allowed_app_ids.set(allowed_app_ids).unwrap();
+        (res.ivars().title_app_ids)
+            .set(title_app_ids.into_iter().collect())
+            .unwrap();
+        (res.ivars().per_window_app_ids)
+            .set(per_window_app_ids.into_iter().collect())
+            .unwrap();
         res.start();
         res
     }
@@ -151,8 +171,9 @@ impl WorkspaceObserver {
 
         let ivars = self.ivars();
 
-        let new = ivars.workspace.runningApplications();
-        let new_keys = self.window_change_pids(&new.to_vec());
+        let running = ivars.workspace.runningApplications();
+        let observed = self.window_change_pids(&running.to_vec());
+        let new_keys = observed.keys().copied().collect::<HashSet<_>>();
 
         let mut children = ivars.children.lock().expect("failed to lock children");
         let old_keys = children.keys().copied().collect::<HashSet<_>>();
@@ -163,13 +184,47 @@ impl WorkspaceObserver {
         }
         for pid in new_keys.difference(&old_keys) {
             trace!("adding to children: {pid}");
+            let watch = observed[pid];
             _ = WindowObserver::try_new(
                 *pid,
-                Box::new(|obs, notif| {
+                Box::new(move |obs, notif, elem| {
+                    let pid = NSNumber::new_i32(obs.pid());
                     #[allow(non_upper_case_globals)]
                     let name = match notif.as_ref() {
-                        kAXFocusedWindowChangedNotification => FOCUSED_WINDOW_CHANGED_NOTIFICATION,
+                        kAXFocusedWindowChangedNotification if watch.popup => {
+                            FOCUSED_WINDOW_CHANGED_NOTIFICATION
+                        }
+                        // Without popup detection, the focused window only matters
+                        // for its title and identity, which are only considered if
+                        // the app is in focus.
+                        kAXFocusedWindowChangedNotification | kAXTitleChangedNotification => {
+                            TITLE_CHANGED_NOTIFICATION
+                        }
                         kAXApplicationHiddenNotification => APP_HIDDEN_NOTIFICATION,
+                        kAXWindowCreatedNotification => {
+                            _ = obs.watch_window(elem).inspect_err(|e| {
+                                debug!("failed to watch new window of PID {}: {e}", obs.pid());
+                            });
+                            return;
+                        }
+                        kAXUIElementDestroyedNotification => {
+                            let Some(number) = obs.forget_window(elem) else {
+                                return;
+                            };
+                            let number = NSNumber::new_u32(number);
+                            unsafe {
+                                let info = NSDictionary::from_slices(
+                                    &[ns_string!(WINDOW_NUMBER_KEY)],
+                                    &[&*number],
+                                );
+                                LOCAL_NOTIFICATION_CENTER.postNotificationName_object_userInfo(
+                                    &NSNotificationName::from_str(WINDOW_CLOSED_NOTIFICATION),
+                                    Some(&pid),
+                                    Some(&Retained::cast_unchecked::<NSDictionary>(info)),
+                                );
+                            };
+                            return;
+                        }
                         notif => {
                             debug!("unexpected notification `{notif}` detected");
                             return;
@@ -178,15 +233,21 @@ impl WorkspaceObserver {
                     unsafe {
                         LOCAL_NOTIFICATION_CENTER.postNotificationName_object(
                             &NSNotificationName::from_str(name),
-                            Some(&NSNumber::new_i32(obs.pid())),
+                            Some(&pid),
                         );
                     };
                 }),
             )
             .and_then(|mut new| {
-                new.as_mut()
-                    .subscribe(kAXFocusedWindowChangedNotification)?;
-                new.as_mut().subscribe(kAXApplicationHiddenNotification)?;
+                for notif in watch.notifs() {
+                    new.as_mut().subscribe(notif)?;
+                }
+                if watch.window {
+                    // The windows opened later on are watched as they are created.
+                    _ = new.watch_windows().inspect_err(|e| {
+                        debug!("failed to watch windows of PID {pid}: {e}");
+                    });
+                }
                 new.start();
                 children.insert(*pid, new);
                 Ok(())
@@ -196,10 +257,28 @@ impl WorkspaceObserver {
         drop(children);
     }
 
+    /// Returns what to observe from the app identified by `app_id`.
+    fn watch(&self, app_id: &str) -> Watch {
+        if Self::EXCLUDED_APP_IDS.contains(&app_id) {
+            return Watch::default();
+        }
+        let matches = |pats: &OnceLock<Vec<AppPattern>>| {
+            (pats.get().unwrap().iter()).any(|pat| pat.matches(app_id))
+        };
+        let ivars = self.ivars();
+        Watch {
+            popup: matches(&ivars.allowed_app_ids),
+            title: matches(&ivars.title_app_ids),
+            window: matches(&ivars.per_window_app_ids),
+        }
+    }
+
+    /// Returns the PIDs of the windowed apps to observe, along with what to
+    /// observe from each of them.
     fn window_change_pids(
         &self,
         running_apps: &[Retained<NSRunningApplication>],
-    ) -> HashSet<pid_t> {
+    ) -> HashMap<pid_t, Watch> {
         // https://apple.stackexchange.com/a/317705
         // https://gist.github.com/ljos/3040846
         // https://stackoverflow.com/a/61688877
@@ -216,21 +295,47 @@ impl WorkspaceObserver {
 
         running_apps
             .iter()
-            .filter(|&app| {
-                app.bundleIdentifier().is_some_and(|nss| {
-                    self.ivars()
-                        .allowed_app_ids
-                        .get()
-                        .unwrap()
-                        .contains(&nss.to_string())
-                })
+            .filter(|app| windowed_pids.contains(&app.processIdentifier()))
+            .filter_map(|app| {
+                let watch = self.watch(&app.bundleIdentifier()?.to_string());
+                (watch != Watch::default()).then(|| (app.processIdentifier(), watch))
             })
-            .map(|app| app.processIdentifier())
-            .filter(|pid| windowed_pids.contains(pid))
             .collect()
     }
 }
 
+/// What to observe from an app.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Watch {
+    /// Whether to detect popup windows from the app.
+    popup: bool,
+    /// Whether to track the title of the focused window of the app.
+    title: bool,
+    /// Whether to track the focused window of the app and the closing of
+    /// its windows.
+    window: bool,
+}
+
+impl Watch {
+    /// Returns the AX notifications to subscribe to.
+    fn notifs(self) -> Vec<&'static str> {
+        let mut notifs = vec![];
+        if self.popup || self.title || self.window {
+            notifs.push(kAXFocusedWindowChangedNotification);
+        }
+        if self.popup {
+            notifs.push(kAXApplicationHiddenNotification);
+        }
+        if self.title {
+            notifs.push(kAXTitleChangedNotification);
+        }
+        if self.window {
+            notifs.push(kAXWindowCreatedNotification);
+        }
+        notifs
+    }
+}
+
 impl Drop for WorkspaceObserver {
     fn drop(&mut self) {
         self.stop();
@@ -0,0 +1,198 @@
+use std::{fmt, str::FromStr};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// A pattern matching app IDs, or any other string such as window titles.
+///
+/// The syntax is as follows:
+/// - `/<regex>/` matches app IDs against the regular expression `<regex>`, e.g.
+///   `/^org\.mozilla\.(firefox|nightly)$/`. The expression is not anchored
+///   implicitly.
+/// - Any string containing `*` or `?` is treated as a glob, where `*` matches
+///   any sequence of characters and `?` matches any single character, e.g.
+///   `com.jetbrains.*`. Globs always match the whole app ID.
+/// - Any other string matches an app ID exactly, e.g. `com.apple.Safari`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AppPattern {
+    src: String,
+    kind: PatternKind,
+}
+
+#[derive(Clone, Debug)]
+enum PatternKind {
+    Exact,
+    Glob(Regex),
+    Regex(Regex),
+}
+
+impl AppPattern {
+    /// Returns the string this pattern has been parsed from.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.src
+    }
+
+    /// Returns if this pattern only matches a single app ID.
+    #[must_use]
+    pub const fn is_exact(&self) -> bool {
+        matches!(self.kind, PatternKind::Exact)
+    }
+
+    #[must_use]
+    pub fn matches(&self, app: &str) -> bool {
+        match &self.kind {
+            PatternKind::Exact => self.src == app,
+            PatternKind::Glob(re) | PatternKind::Regex(re) => re.is_match(app),
+        }
+    }
+
+    /// Returns the first of `items` whose pattern matches `app`.
+    ///
+    /// Exact patterns take precedence over the others, regardless of their
+    /// order in `items`; otherwise, the first match wins.
+    pub fn find<'a, T>(
+        items: impl IntoIterator<Item = &'a T>,
+        pattern: impl Fn(&T) -> &Self,
+        app: &str,
+    ) -> Option<&'a T> {
+        let mut first_match = None;
+        for it in items {
+            let pat = pattern(it);
+            if !pat.matches(app) {
+                continue;
+            }
+            if pat.is_exact() {
+                return Some(it);
+            }
+            first_match.get_or_insert(it);
+        }
+        first_match
+    }
+}
+
+impl FromStr for AppPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |e| Error::InvalidPattern(s.to_owned(), e);
+        let kind = if let Some(re) = s
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|re| !re.is_empty())
+        {
+            PatternKind::Regex(Regex::new(re).map_err(invalid)?)
+        } else if s.contains(['*', '?']) {
+            let re = s
+                .split('*')
+                .map(|part| {
+                    part.split('?')
+                        .map(regex::escape)
+                        .collect::<Vec<_>>()
+                        .join(".")
+                })
+                .collect::<Vec<_>>()
+                .join(".*");
+            PatternKind::Glob(Regex::new(&format!("^{re}$")).map_err(invalid)?)
+        } else {
+            PatternKind::Exact
+        };
+        Ok(Self {
+            src: s.to_owned(),
+            kind,
+        })
+    }
+}
+
+impl TryFrom<String> for AppPattern {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<AppPattern> for String {
+    fn from(pat: AppPattern) -> Self {
+        pat.src
+    }
+}
+
+impl AsRef<str> for AppPattern {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for AppPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.src)
+    }
+}
+
+impl PartialEq for AppPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.src == other.src
+    }
+}
+
+impl Eq for AppPattern {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pat(s: &str) -> AppPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_exact() {
+        let p = pat("com.apple.Safari");
+        assert!(p.is_exact());
+        assert!(p.matches("com.apple.Safari"));
+        assert!(!p.matches("com.apple.SafariTechnologyPreview"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let p = pat("com.jetbrains.*");
+        assert!(!p.is_exact());
+        assert!(p.matches("com.jetbrains.intellij"));
+        assert!(p.matches("com.jetbrains.rustrover-EAP"));
+        assert!(!p.matches("com_jetbrains.intellij"));
+        assert!(!p.matches("org.com.jetbrains.intellij"));
+
+        let p = pat("org.mozilla.firefox?");
+        assert!(p.matches("org.mozilla.firefoxb"));
+        assert!(!p.matches("org.mozilla.firefox"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let p = pat(r"/^org\.mozilla\.(firefox|nightly)$/");
+        assert!(p.matches("org.mozilla.nightly"));
+        assert!(!p.matches("org.mozilla.thunderbird"));
+        assert!(matches!(
+            "/(/".parse::<AppPattern>(),
+            Err(Error::InvalidPattern(..))
+        ));
+    }
+
+    #[test]
+    fn test_find_precedence() {
+        let pats = [
+            pat("com.jetbrains.*"),
+            pat("/jetbrains/"),
+            pat("com.jetbrains.pycharm"),
+        ];
+        let find = |app| AppPattern::find(&pats, |p| p, app).map(AppPattern::as_str);
+        assert_eq!(find("com.jetbrains.pycharm"), Some("com.jetbrains.pycharm"));
+        assert_eq!(find("com.jetbrains.goland"), Some("com.jetbrains.*"));
+        assert_eq!(find("io.jetbrains.fleet"), Some("/jetbrains/"));
+        assert_eq!(find("com.apple.Safari"), None);
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{LineWriter, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    backend::{InputSourceBackend, fake::FakeBackend},
+    debounce::Debouncer,
+    engine::{Action, Engine, Event},
+    error::Result,
+    focus::{AppId, FocusEvent, FocusReason, Origin, WindowId},
+};
+
+/// An event handled by the daemon, as written to a recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Recorded {
+    /// A [`FocusEvent`], along with the input source that was current when
+    /// it has been received.
+    Focus {
+        app: AppId,
+        reason: FocusReason,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        window: Option<WindowId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(flatten)]
+        origin: Option<Origin>,
+        source: String,
+    },
+    /// A change of the current input source, along with the app it has been
+    /// attributed to, if any.
+    InputSource { app: Option<AppId>, source: String },
+}
+
+impl Recorded {
+    #[must_use]
+    pub fn focus(event: &FocusEvent, source: impl Into<String>) -> Self {
+        Self::Focus {
+            app: event.app.clone(),
+            reason: event.reason,
+            window: event.window.clone(),
+            title: event.title.clone(),
+            origin: event.origin.clone(),
+            source: source.into(),
+        }
+    }
+}
+
+/// A line of a recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The time the event has been received, in milliseconds since the Unix
+    /// epoch.
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: Recorded,
+}
+
+/// Writes the events handled by the daemon to a file in the JSON Lines
+/// format, to be fed to a [`Replay`] later on.
+#[derive(Debug)]
+pub struct Recorder(LineWriter<File>);
+
+impl Recorder {
+    /// Creates a recorder writing to `path`, truncating it if it exists.
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self(LineWriter::new(File::create(path)?)))
+    }
+
+    pub fn record(&mut self, event: Recorded) -> Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let entry = Entry {
+            time: u64::try_from(time).unwrap_or(u64::MAX),
+            event,
+        };
+        serde_json::to_writer(&mut self.0, &entry)?;
+        self.0.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Reads a recording made by a [`Recorder`] from `path`.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let content = fs::read_to_string(path)?;
+    (content.lines())
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Feeds a recording through an [`Engine`], using a [`FakeBackend`] in place
+/// of the actual input sources.
+///
+/// The backend provides all the input sources mentioned in the recording,
+/// and starts with the one that was current at the beginning of it. Each
+/// focus change is replayed with the input source recorded along with it.
+///
+/// Just like in the daemon, bursts of focus changes are coalesced according
+/// to the settle window in the engine's [`Config`](crate::config::Config),
+/// using the times in the recording as a virtual clock.
+#[must_use]
+#[derive(Debug)]
+pub struct Replay {
+    pub engine: Engine,
+    pub backend: FakeBackend,
+    /// The time of the first entry of the recording.
+    start: u64,
+    /// The instant standing for `start` on the virtual clock.
+    epoch: Instant,
+    debouncer: Debouncer<Entry>,
+}
+
+impl Replay {
+    pub fn new(engine: Engine, entries: &[Entry]) -> Self {
+        let mut sources: Vec<&str> = vec![];
+        for entry in entries {
+            let (Recorded::Focus { source, .. } | Recorded::InputSource { source, .. }) =
+                &entry.event;
+            if !sources.contains(&source.as_str()) {
+                sources.push(source);
+            }
+        }
+        let debouncer = Debouncer::new(engine.config().settle_window());
+        Self {
+            engine,
+            backend: FakeBackend::with_ids(sources),
+            start: entries.first().map_or(0, |e| e.time),
+            epoch: Instant::now(),
+            debouncer,
+        }
+    }
+
+    /// Replays a single entry, returning the decisions made in response.
+    ///
+    /// The pending focus change that has settled by the time of `entry`, if
+    /// any, is handled first.
+    pub fn step(&mut self, entry: &Entry) -> Result<Vec<Decision>> {
+        let now = self.instant(entry.time);
+        let mut decisions = vec![];
+        if let Some(deadline) = self.debouncer.deadline()
+            && deadline <= now
+            && let Some(settled) = self.debouncer.flush()
+        {
+            decisions.push(self.settle(settled, deadline)?);
+        }
+        let actions = match &entry.event {
+            // Closing windows is not subject to coalescing, as in the daemon.
+            Recorded::Focus { reason, .. } if *reason != FocusReason::WindowClosed => {
+                self.debouncer
+                    .set_window(self.engine.config().settle_window());
+                (self.debouncer.push(entry.clone(), now))
+                    .map_or_else(|| Ok(vec![]), |entry| self.handle(&entry))?
+            }
+            _ => self.handle(entry)?,
+        };
+        decisions.push(Decision {
+            start: self.start,
+            entry: entry.clone(),
+            settled: false,
+            actions,
+        });
+        Ok(decisions)
+    }
+
+    /// Handles the focus change still pending at the end of the recording, if
+    /// any.
+    pub fn finish(&mut self) -> Result<Option<Decision>> {
+        let Some(deadline) = self.debouncer.deadline() else {
+            return Ok(None);
+        };
+        let Some(entry) = self.debouncer.flush() else {
+            return Ok(None);
+        };
+        self.settle(entry, deadline).map(Some)
+    }
+
+    /// Handles the focus change `entry` that has settled at `at`.
+    fn settle(&mut self, entry: Entry, at: Instant) -> Result<Decision> {
+        let actions = self.handle(&entry)?;
+        let elapsed = at.saturating_duration_since(self.epoch).as_millis();
+        Ok(Decision {
+            start: self.start,
+            entry: Entry {
+                time: self.start + u64::try_from(elapsed).unwrap_or(u64::MAX),
+                ..entry
+            },
+            settled: true,
+            actions,
+        })
+    }
+
+    /// Returns the instant standing for `time` on the virtual clock.
+    fn instant(&self, time: u64) -> Instant {
+        self.epoch + Duration::from_millis(time.saturating_sub(self.start))
+    }
+
+    /// Feeds `entry` to the engine right away, returning the actions taken in
+    /// response.
+    fn handle(&mut self, entry: &Entry) -> Result<Vec<Action>> {
+        let event = match &entry.event {
+            Recorded::Focus {
+                app,
+                reason: FocusReason::WindowClosed,
+                window,
+                ..
+            } => {
+                let Some(window) = window else {
+                    return Ok(vec![]);
+                };
+                Event::WindowClosed {
+                    app: app.clone(),
+                    window: window.clone(),
+                }
+            }
+            Recorded::Focus {
+                app,
+                window,
+                title,
+                source,
+                ..
+            } => {
+                let current = self.backend.current()?;
+                if current != *source {
+                    // The change might have been missed by the recording, e.g. if
+                    // the daemon has been restarted in the meantime.
+                    warn!(
+                        "replaying with input source `{source}` as recorded instead of `{current}`"
+                    );
+                    self.backend.switch(source);
+                }
+                Event::AppActivated {
+                    app: app.clone(),
+                    window: window.clone(),
+                    title: title.clone(),
+                    source: source.clone(),
+                }
+            }
+            Recorded::InputSource { app, source } => {
+                self.backend.switch(source);
+                let Some(app) = app else {
+                    return Ok(vec![]);
+                };
+                Event::InputSourceChanged {
+                    app: app.clone(),
+                    source: source.clone(),
+                }
+            }
+        };
+        self.engine.drive(&self.backend, event)
+    }
+}
+
+/// A replayed [`Entry`] along with the resulting actions, displayed
+/// relative to the start of the recording.
+#[derive(Clone, Debug)]
+pub struct Decision {
+    /// The time of the first entry of the recording.
+    pub start: u64,
+    /// The entry, timed at when it has been handled.
+    pub entry: Entry,
+    /// Whether `entry` has been handled once its settle window has elapsed
+    /// rather than upon receipt.
+    pub settled: bool,
+    pub actions: Vec<Action>,
+}
+
+impl fmt::Display for Decision {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elapsed = self.entry.time.saturating_sub(self.start) as f64 / 1000.;
+        write!(f, "+{elapsed:.3}s ")?;
+        if self.settled {
+            write!(f, "settled on ")?;
+        }
+        match &self.entry.event {
+            Recorded::Focus {
+                app,
+                reason,
+                window,
+                title,
+                origin,
+                ..
+            } => {
+                write!(f, "focus `{app}`")?;
+                if let Some(window) = window {
+                    write!(f, " in window `{window}`")?;
+                }
+                if let Some(title) = title {
+                    write!(f, " titled {title:?}")?;
+                }
+                write!(f, " via {reason}")?;
+                if let Some(Origin { notification, pid }) = origin {
+                    write!(f, " (`{notification}`")?;
+                    if let Some(pid) = pid {
+                        write!(f, ", PID {pid}")?;
+                    }
+                    write!(f, ")")?;
+                }
+            }
+            Recorded::InputSource { app, source } => {
+                write!(f, "input source `{source}`")?;
+                match app {
+                    Some(app) => write!(f, " in `{app}`")?,
+                    None => write!(f, " in an unknown app")?,
+                }
+            }
+        }
+        for action in &self.actions {
+            match action {
+                Action::Select { app, source } => {
+                    write!(f, "\n    select `{source}` for `{app}`")?;
+                }
+                Action::Record { app, source } => {
+                    write!(f, "\n    record `{source}` for `{app}`")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, state::InputSourceState};
+
+    const ABC: &str = "com.apple.keylayout.ABC";
+    const PINYIN: &str = "com.apple.inputmethod.SCIM.ITABC";
+
+    #[test]
+    fn test_record_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut recorder = Recorder::create(&path).unwrap();
+        let event = FocusEvent::new("com.apple.Safari", FocusReason::AppActivated)
+            .with_origin("NSWorkspaceDidActivateApplicationNotification", Some(42));
+        recorder.record(Recorded::focus(&event, ABC)).unwrap();
+        recorder
+            .record(Recorded::InputSource {
+                app: None,
+                source: PINYIN.into(),
+            })
+            .unwrap();
+        drop(recorder);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let first: serde_json::Value =
+            serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(first["type"], "focus");
+        assert_eq!(first["reason"], "app-activated");
+        assert_eq!(
+            first["notification"],
+            "NSWorkspaceDidActivateApplicationNotification"
+        );
+        assert_eq!(first["pid"], 42);
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].event, Recorded::focus(&event, ABC));
+        assert!(entries[0].time <= entries[1].time);
+    }
+
+    fn parse(lines: &[&str]) -> Vec<Entry> {
+        (lines.iter())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn replay_all(replay: &mut Replay, entries: &[Entry]) -> Vec<String> {
+        let mut decisions = vec![];
+        for entry in entries {
+            decisions.extend(replay.step(entry).unwrap());
+        }
+        decisions.extend(replay.finish().unwrap());
+        decisions.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_replay() {
+        let entries = parse(&[
+            r#"{"time": 1000, "type": "focus", "app": "foo", "reason": "app-activated", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 1200, "type": "input-source", "app": "foo", "source": "com.apple.inputmethod.SCIM.ITABC"}"#,
+            r#"{"time": 2000, "type": "focus", "app": "bar", "reason": "app-activated", "title": "Inbox", "notification": "NSWorkspaceDidActivateApplicationNotification", "pid": 7, "source": "com.apple.inputmethod.SCIM.ITABC"}"#,
+            r#"{"time": 2200, "type": "input-source", "app": "bar", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 2500, "type": "focus", "app": "foo", "reason": "app-hidden", "source": "com.apple.keylayout.ABC"}"#,
+        ]);
+
+        let mut replay = Replay::new(Engine::new(InputSourceState::new()), &entries);
+        assert_eq!(
+            replay_all(&mut replay, &entries),
+            [
+                format!("+0.000s focus `foo` via app activation\n    record `{ABC}` for `foo`"),
+                format!(
+                    "+0.200s input source `{PINYIN}` in `foo`\n    record `{PINYIN}` for `foo`"
+                ),
+                format!(
+                    "+1.000s focus `bar` titled \"Inbox\" via app activation \
+                    (`NSWorkspaceDidActivateApplicationNotification`, PID 7)\n    \
+                    record `{PINYIN}` for `bar`"
+                ),
+                format!("+1.200s input source `{ABC}` in `bar`\n    record `{ABC}` for `bar`"),
+                format!("+1.500s focus `foo` via app hiding\n    select `{PINYIN}` for `foo`"),
+            ]
+        );
+        assert_eq!(replay.backend.selections(), [PINYIN]);
+    }
+
+    #[test]
+    fn test_replay_recorded_source() {
+        // The switch to Pinyin in `foo` is missing from the recording.
+        let entries = parse(&[
+            r#"{"time": 1000, "type": "focus", "app": "foo", "reason": "app-activated", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 1500, "type": "focus", "app": "bar", "reason": "app-activated", "source": "com.apple.inputmethod.SCIM.ITABC"}"#,
+        ]);
+
+        let mut replay = Replay::new(Engine::new(InputSourceState::new()), &entries);
+        assert_eq!(
+            replay_all(&mut replay, &entries),
+            [
+                format!("+0.000s focus `foo` via app activation\n    record `{ABC}` for `foo`"),
+                format!("+0.500s focus `bar` via app activation\n    record `{PINYIN}` for `bar`"),
+            ]
+        );
+        assert_eq!(replay.backend.current().unwrap(), PINYIN);
+    }
+
+    #[test]
+    fn test_replay_settle_window() {
+        let entries = parse(&[
+            r#"{"time": 1000, "type": "focus", "app": "bar", "reason": "app-activated", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 1010, "type": "focus", "app": "foo", "reason": "focused-window-changed", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 1020, "type": "focus", "app": "bar", "reason": "app-activated", "source": "com.apple.keylayout.ABC"}"#,
+            r#"{"time": 1200, "type": "input-source", "app": "bar", "source": "com.apple.inputmethod.SCIM.ITABC"}"#,
+            r#"{"time": 2000, "type": "focus", "app": "foo", "reason": "app-activated", "source": "com.apple.inputmethod.SCIM.ITABC"}"#,
+        ]);
+        let state = InputSourceState::new();
+        state.save("foo".into(), ABC.into());
+        state.save("bar".into(), ABC.into());
+        let engine = Engine::new(state).with_config(Config {
+            settle_ms: 50,
+            ..Config::default()
+        });
+        let mut replay = Replay::new(engine, &entries);
+        assert_eq!(
+            replay_all(&mut replay, &entries),
+            [
+                "+0.000s focus `bar` via app activation".to_owned(),
+                "+0.010s focus `foo` via focused window change".to_owned(),
+                "+0.020s focus `bar` via app activation".to_owned(),
+                "+0.070s settled on focus `bar` via app activation".to_owned(),
+                format!(
+                    "+0.200s input source `{PINYIN}` in `bar`\n    record `{PINYIN}` for `bar`"
+                ),
+                "+1.000s focus `foo` via app activation".to_owned(),
+                format!(
+                    "+1.050s settled on focus `foo` via app activation\n    select `{ABC}` for `foo`"
+                ),
+            ]
+        );
+        assert_eq!(replay.backend.selections(), [ABC]);
+    }
+}
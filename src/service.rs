@@ -1,9 +1,10 @@
 use std::{
-    env, fs,
-    io::Write,
+    collections::BTreeMap,
+    fmt, fs, io,
     path::{Path, PathBuf},
 };
 
+use similar::TextDiff;
 use tracing::{info, warn};
 
 use crate::{
@@ -11,66 +12,300 @@ use crate::{
     util::exe_path,
 };
 
+pub mod launchd;
+pub mod systemd;
+
 pub const ID: &str = "io.github.rami3l.clavy";
 
+/// What a [`Service`] runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceSpec {
+    /// The path of the `clavy` binary.
+    pub bin_path: PathBuf,
+    /// The bundle IDs (or patterns thereof) to detect popup windows from, if
+    /// given.
+    pub detect_popup: Option<Vec<String>>,
+    /// The path of the configuration file, if not the default one.
+    pub config_path: Option<PathBuf>,
+    /// Additional environment variables to pass to the daemon.
+    pub env: BTreeMap<String, String>,
+    /// The file to redirect the standard output of the daemon to, if not
+    /// the default one of the service manager.
+    pub stdout_path: Option<PathBuf>,
+    /// The file to redirect the standard error of the daemon to, if not the
+    /// default one of the service manager.
+    pub stderr_path: Option<PathBuf>,
+    /// The niceness of the daemon, if not the default one of the service
+    /// manager.
+    pub nice: Option<i32>,
+    /// Whether the daemon should be started as soon as the service is loaded,
+    /// e.g. at login.
+    pub run_at_load: bool,
+}
+
+impl Default for ServiceSpec {
+    fn default() -> Self {
+        Self {
+            bin_path: PathBuf::new(),
+            detect_popup: None,
+            config_path: None,
+            env: BTreeMap::new(),
+            stdout_path: None,
+            stderr_path: None,
+            nice: None,
+            run_at_load: true,
+        }
+    }
+}
+
+impl ServiceSpec {
+    /// Returns the environment variables to pass to the daemon, including
+    /// the ones carrying the options in this spec.
+    #[must_use]
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars = vec![("NO_COLOR".into(), "1".into())];
+        if let Some(detect_popup) = &self.detect_popup {
+            vars.push(("CLAVY_DETECT_POPUP".into(), join(detect_popup, ",")));
+        }
+        if let Some(config_path) = &self.config_path {
+            vars.push((
+                "CLAVY_CONFIG".into(),
+                config_path.to_string_lossy().into_owned(),
+            ));
+        }
+        vars.extend(self.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        vars
+    }
+
+    /// Records the environment variable `key` as returned by
+    /// [`Self::env_vars`].
+    fn set_env_var(&mut self, key: String, val: String) {
+        match &*key {
+            "NO_COLOR" => (),
+            "CLAVY_DETECT_POPUP" => {
+                self.detect_popup = Some(if val.is_empty() {
+                    vec![]
+                } else {
+                    val.split(',').map(ToOwned::to_owned).collect()
+                });
+            }
+            "CLAVY_CONFIG" => self.config_path = Some(val.into()),
+            _ => _ = self.env.insert(key, val),
+        }
+    }
+}
+
+/// A difference between the installed service definition and the one that
+/// would be installed now.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// A setting of the daemon has changed, e.g. the path of the binary
+    /// after an upgrade.
+    Setting {
+        name: &'static str,
+        installed: String,
+        current: String,
+    },
+    /// The definition has been edited by hand or rendered by another
+    /// version of clavy.
+    Format,
+    /// The definition cannot be parsed.
+    Malformed,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Setting {
+                name,
+                installed,
+                current,
+            } => write!(f, "{name} is `{installed}` instead of `{current}`"),
+            Self::Format => write!(f, "the definition differs from the one rendered by clavy"),
+            Self::Malformed => write!(f, "the definition cannot be parsed"),
+        }
+    }
+}
+
+/// A platform-specific service manager, such as `launchd` or `systemd`.
+pub trait ServiceManager: fmt::Debug {
+    /// Returns the path of the service definition, e.g. the launch agent.
+    fn definition_path(&self) -> &Path;
+
+    /// Renders the service definition running `spec`.
+    fn render(&self, spec: &ServiceSpec) -> String;
+
+    /// Parses a service definition rendered by [`Self::render`] back into
+    /// the spec it runs, returning `None` if `definition` is malformed.
+    fn parse(&self, definition: &str) -> Option<ServiceSpec>;
+
+    /// Returns if the service has been loaded by the service manager.
+    fn is_loaded(&self) -> bool;
+
+    /// Loads and starts the installed service.
+    fn start(&self) -> Result<()>;
+
+    /// Stops and unloads the service.
+    fn stop(&self) -> Result<()>;
+}
+
 #[derive(Debug)]
 pub struct Service {
-    pub raw: launchctl::Service,
-    pub bin_path: PathBuf,
-    pub detect_popup: String,
+    pub manager: Box<dyn ServiceManager>,
+    pub spec: ServiceSpec,
 }
 
 impl Service {
+    /// Creates the service running the current executable under the service
+    /// manager of the current platform.
     pub fn try_new<S: AsRef<str>>(
-        name: &str,
-        detect_popup: impl IntoIterator<Item = S>,
+        detect_popup: Option<impl IntoIterator<Item = S>>,
+        config_path: Option<PathBuf>,
     ) -> Result<Self> {
-        Ok(Self {
+        let spec = ServiceSpec {
             bin_path: exe_path().ok_or(Error::FaultyExePath)?,
-            detect_popup: join(detect_popup, ","),
-            raw: launchctl::Service::builder()
-                .name(name)
-                .uid(unsafe { libc::getuid() }.to_string())
-                .plist_path(format!(
-                    "{home}/Library/LaunchAgents/{name}.plist",
-                    home = env::home_dir().ok_or(Error::HomeNotSet)?.display()
-                ))
-                .build(),
-        })
+            detect_popup: detect_popup
+                .map(|pats| (pats.into_iter()).map(|s| s.as_ref().to_owned()).collect()),
+            config_path,
+            ..ServiceSpec::default()
+        };
+        let manager: Box<dyn ServiceManager> = if cfg!(target_os = "macos") {
+            Box::new(launchd::Launchd::try_new(ID)?)
+        } else {
+            Box::new(systemd::Systemd::try_new()?)
+        };
+        Ok(Self { manager, spec })
     }
 
     #[must_use]
-    pub fn plist_path(&self) -> &Path {
-        Path::new(&self.raw.plist_path)
+    pub fn definition_path(&self) -> &Path {
+        self.manager.definition_path()
     }
 
     #[must_use]
     pub fn is_installed(&self) -> bool {
-        self.plist_path().is_file()
+        self.definition_path().is_file()
     }
 
+    #[must_use]
+    pub fn is_loaded(&self) -> bool {
+        self.manager.is_loaded()
+    }
+
+    /// Renders the service definition.
+    #[must_use]
+    pub fn render(&self) -> String {
+        self.manager.render(&self.spec)
+    }
+
+    /// Installs the service.
+    ///
+    /// If the service has already been installed but has drifted from the
+    /// current one, its definition is repaired while keeping the options it
+    /// has been installed with.
     pub fn install(&self) -> Result<()> {
-        let plist_path = self.plist_path();
+        let path = self.definition_path();
         if self.is_installed() {
-            warn!(
-                "existing launch agent detected at `{}`, skipping installation",
-                plist_path.display()
+            let (installed, drifts) = self.check()?;
+            if drifts.is_empty() {
+                warn!(
+                    "existing service detected at `{}`, skipping installation",
+                    path.display()
+                );
+                return Ok(());
+            }
+            for drift in &drifts {
+                warn!(
+                    "existing service at `{}` has drifted: {drift}",
+                    path.display()
+                );
+            }
+            let spec = installed.map_or_else(
+                || self.spec.clone(),
+                |installed| ServiceSpec {
+                    bin_path: self.spec.bin_path.clone(),
+                    detect_popup: (self.spec.detect_popup.clone())
+                        .or_else(|| installed.detect_popup.clone()),
+                    ..installed
+                },
             );
+            fs::write(path, self.manager.render(&spec))?;
+            info!("repaired service at `{}`", path.display());
+            if self.is_loaded() {
+                warn!("restart the service for the changes to take effect");
+            }
             return Ok(());
         }
 
-        let mut plist = fs::File::create(plist_path)?;
-        plist.write_all(self.launchd_plist().as_bytes())?;
-        info!("installed launch agent to `{}`", plist_path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.render())?;
+        info!("installed service to `{}`", path.display());
         Ok(())
     }
 
+    /// Returns the differences between the installed service definition and
+    /// the current one.
+    ///
+    /// Only the settings of the daemon that are not install-time options are
+    /// compared, so a service installed with e.g. a custom niceness does not
+    /// count as drifted.
+    pub fn verify(&self) -> Result<Vec<Drift>> {
+        Ok(self.check()?.1)
+    }
+
+    /// Parses the installed service definition, returning the spec it runs
+    /// if it is well-formed along with its [`Drift`]s.
+    fn check(&self) -> Result<(Option<ServiceSpec>, Vec<Drift>)> {
+        let path = self.definition_path();
+        let definition = match fs::read_to_string(path) {
+            Ok(definition) => definition,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::ServiceNotInstalled(path.to_owned()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let Some(installed) = self.manager.parse(&definition) else {
+            return Ok((None, vec![Drift::Malformed]));
+        };
+
+        let mut drifts = vec![];
+        let mut compare = |name, installed: String, current: String| {
+            if installed != current {
+                drifts.push(Drift::Setting {
+                    name,
+                    installed,
+                    current,
+                });
+            }
+        };
+        compare(
+            "the binary path",
+            installed.bin_path.display().to_string(),
+            self.spec.bin_path.display().to_string(),
+        );
+        // Like the configuration path, the popup detection list is only
+        // compared if it has been given again.
+        if let Some(detect_popup) = &self.spec.detect_popup {
+            compare(
+                "CLAVY_DETECT_POPUP",
+                join(installed.detect_popup.iter().flatten(), ","),
+                join(detect_popup, ","),
+            );
+        }
+        if drifts.is_empty() && self.manager.render(&installed) != definition {
+            drifts.push(Drift::Format);
+        }
+        Ok((Some(installed), drifts))
+    }
+
     pub fn uninstall(&self) -> Result<()> {
-        let plist_path = self.plist_path();
+        let path = self.definition_path();
         if !self.is_installed() {
             warn!(
-                "no launch agent detected at `{}`, skipping uninstallation",
-                plist_path.display(),
+                "no service detected at `{}`, skipping uninstallation",
+                path.display(),
             );
             return Ok(());
         }
@@ -79,32 +314,57 @@ impl Service {
             warn!("failed to stop service: {e:?}");
         }
 
-        fs::remove_file(plist_path)?;
-        info!(
-            "removed existing launch agent at `{}`",
-            plist_path.display()
-        );
+        fs::remove_file(path)?;
+        info!("removed existing service at `{}`", path.display());
         Ok(())
     }
 
+    /// Returns the changes that installing the service would make to the
+    /// installed service definition as a unified diff, or `None` if there
+    /// would be none.
+    pub fn diff(&self) -> Result<Option<String>> {
+        let path = self.definition_path();
+        let installed = match fs::read_to_string(path) {
+            Ok(installed) => installed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let rendered = self.render();
+        if installed == rendered {
+            return Ok(None);
+        }
+        let path = path.display().to_string();
+        Ok(Some(
+            TextDiff::from_lines(&installed, &rendered)
+                .unified_diff()
+                .header(&format!("{path} (installed)"), &format!("{path} (new)"))
+                .to_string(),
+        ))
+    }
+
     pub fn reinstall(&self) -> Result<()> {
         self.uninstall()?;
         self.install()
     }
 
     pub fn start(&self) -> Result<()> {
-        if !self.is_installed() {
+        if self.is_installed() {
+            for drift in self.verify()? {
+                warn!("installed service has drifted: {drift}");
+                warn!("run `clavy install` to repair it");
+            }
+        } else {
             self.install()?;
         }
         info!("starting service...");
-        self.raw.start()?;
+        self.manager.start()?;
         info!("service started");
         Ok(())
     }
 
     pub fn stop(&self) -> Result<()> {
         info!("stopping service...");
-        self.raw.stop()?;
+        self.manager.stop()?;
         info!("service stopped");
         Ok(())
     }
@@ -113,18 +373,6 @@ impl Service {
         self.stop()?;
         self.start()
     }
-
-    #[must_use]
-    pub fn launchd_plist(&self) -> String {
-        format!(
-            include_str!("../assets/launchd.plist"),
-            name = self.raw.name,
-            bin_path = self.bin_path.display(),
-            out_log_path = self.raw.out_log_path,
-            error_log_path = self.raw.error_log_path,
-            detect_popup = self.detect_popup,
-        )
-    }
 }
 
 fn join<S: AsRef<str>>(ss: impl IntoIterator<Item = S>, sep: &str) -> String {
@@ -142,6 +390,141 @@ fn join<S: AsRef<str>>(ss: impl IntoIterator<Item = S>, sep: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = Service {
+            manager: Box::new(systemd::Systemd::with_unit_path(
+                dir.path().join("clavy.service"),
+            )),
+            spec: ServiceSpec {
+                bin_path: "/usr/bin/clavy".into(),
+                ..ServiceSpec::default()
+            },
+        };
+        let diff = service.diff().unwrap().unwrap();
+        assert!(diff.contains("+ExecStart=\"/usr/bin/clavy\"\n"));
+
+        service.install().unwrap();
+        assert_eq!(service.diff().unwrap(), None);
+
+        service.spec.nice = Some(5);
+        service.spec.env.insert("RUST_LOG".into(), "debug".into());
+        let diff = service.diff().unwrap().unwrap();
+        let changes: Vec<_> = (diff.lines())
+            .filter(|l| l.starts_with(['+', '-']))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                &format!("--- {} (installed)", service.definition_path().display()),
+                &format!("+++ {} (new)", service.definition_path().display()),
+                "+Nice=5",
+                "+Environment=\"RUST_LOG=debug\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_and_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = Service {
+            manager: Box::new(systemd::Systemd::with_unit_path(
+                dir.path().join("clavy.service"),
+            )),
+            spec: ServiceSpec {
+                bin_path: "/usr/local/Cellar/clavy/1.0.0/bin/clavy".into(),
+                detect_popup: Some(vec!["org.kde.krunner".into()]),
+                nice: Some(5),
+                ..ServiceSpec::default()
+            },
+        };
+        assert!(matches!(
+            service.verify(),
+            Err(Error::ServiceNotInstalled(_))
+        ));
+        service.install().unwrap();
+        assert_eq!(service.verify().unwrap(), []);
+
+        // An upgrade changes the path of the binary, and install-time options
+        // are not passed again.
+        service.spec = ServiceSpec {
+            bin_path: "/usr/local/Cellar/clavy/1.1.0/bin/clavy".into(),
+            ..ServiceSpec::default()
+        };
+        assert_eq!(
+            service.verify().unwrap(),
+            [Drift::Setting {
+                name: "the binary path",
+                installed: "/usr/local/Cellar/clavy/1.0.0/bin/clavy".into(),
+                current: "/usr/local/Cellar/clavy/1.1.0/bin/clavy".into(),
+            }]
+        );
+        service.install().unwrap();
+        assert_eq!(service.verify().unwrap(), []);
+        let repaired = fs::read_to_string(service.definition_path()).unwrap();
+        assert!(repaired.contains("1.1.0"));
+        assert!(repaired.contains("Nice=5"));
+        assert!(repaired.contains("CLAVY_DETECT_POPUP=org.kde.krunner"));
+
+        // An explicitly given popup detection list is compared and repaired.
+        service.spec.detect_popup = Some(vec![]);
+        assert_eq!(
+            service.verify().unwrap(),
+            [Drift::Setting {
+                name: "CLAVY_DETECT_POPUP",
+                installed: "org.kde.krunner".into(),
+                current: String::new(),
+            }]
+        );
+        service.install().unwrap();
+        assert_eq!(service.verify().unwrap(), []);
+        let repaired = fs::read_to_string(service.definition_path()).unwrap();
+        assert!(repaired.contains("\"CLAVY_DETECT_POPUP=\""));
+
+        let path = service.definition_path().to_owned();
+        fs::write(
+            &path,
+            repaired.replace("Restart=on-failure", "Restart=always"),
+        )
+        .unwrap();
+        assert_eq!(service.verify().unwrap(), [Drift::Format]);
+        fs::write(&path, "[Service]\n").unwrap();
+        assert_eq!(service.verify().unwrap(), [Drift::Malformed]);
+        service.install().unwrap();
+        assert_eq!(service.verify().unwrap(), []);
+    }
+
+    #[test]
+    fn test_keep_config_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut service = Service {
+            manager: Box::new(systemd::Systemd::with_unit_path(
+                dir.path().join("clavy.service"),
+            )),
+            spec: ServiceSpec {
+                bin_path: "/usr/bin/clavy".into(),
+                config_path: Some("/etc/clavy/config.toml".into()),
+                ..ServiceSpec::default()
+            },
+        };
+        service.install().unwrap();
+
+        // `clavy start` and co. do not pass `--config` again.
+        service.spec.config_path = None;
+        assert_eq!(service.verify().unwrap(), []);
+        service.install().unwrap();
+
+        // Repairing the binary path keeps the configuration path.
+        service.spec.bin_path = "/usr/local/bin/clavy".into();
+        assert_eq!(service.verify().unwrap().len(), 1);
+        service.install().unwrap();
+        assert_eq!(service.verify().unwrap(), []);
+        let repaired = fs::read_to_string(service.definition_path()).unwrap();
+        assert!(repaired.contains("/usr/local/bin/clavy"));
+        assert!(repaired.contains("CLAVY_CONFIG=/etc/clavy/config.toml"));
+    }
+
     #[test]
     fn test_join() {
         assert_eq!(join(["foo", "baar", "bz", "", "5"], ","), "foo,baar,bz,,5");
@@ -0,0 +1,209 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use plist::{Dictionary, Value};
+
+use super::{ServiceManager, ServiceSpec};
+use crate::error::{Error, Result};
+
+/// The niceness of the agent unless specified otherwise.
+pub const DEFAULT_NICE: i32 = -20;
+
+/// The [`ServiceManager`] running the service as a `launchd` agent.
+#[derive(Debug)]
+pub struct Launchd {
+    pub raw: launchctl::Service,
+    /// The minimum number of seconds between two launches of the agent.
+    pub throttle_interval: Option<u64>,
+    /// The type of the sessions to load the agent into, e.g. `Aqua` for GUI
+    /// sessions.
+    pub session_type: Option<String>,
+}
+
+impl Launchd {
+    /// Creates the launch agent labelled `name` for the current user.
+    pub fn try_new(name: &str) -> Result<Self> {
+        let raw = launchctl::Service::builder()
+            .name(name)
+            .uid(unsafe { libc::getuid() }.to_string())
+            .plist_path(format!(
+                "{home}/Library/LaunchAgents/{name}.plist",
+                home = env::home_dir().ok_or(Error::HomeNotSet)?.display()
+            ))
+            .build();
+        Ok(Self::new(raw))
+    }
+
+    /// Wraps `raw`, loading the agent into GUI sessions only.
+    #[must_use]
+    pub fn new(raw: launchctl::Service) -> Self {
+        Self {
+            raw,
+            throttle_interval: None,
+            session_type: Some("Aqua".into()),
+        }
+    }
+
+    /// Returns the property list of the launch agent running `spec`.
+    #[must_use]
+    pub fn launchd_plist(&self, spec: &ServiceSpec) -> Value {
+        let mut env = Dictionary::new();
+        for (key, val) in spec.env_vars() {
+            env.insert(key, val.into());
+        }
+
+        let mut keep_alive = Dictionary::new();
+        keep_alive.insert("Crashed".into(), true.into());
+        keep_alive.insert("SuccessfulExit".into(), false.into());
+
+        let mut plist = Dictionary::new();
+        plist.insert("Label".into(), self.raw.name.clone().into());
+        plist.insert(
+            "Program".into(),
+            spec.bin_path.to_string_lossy().into_owned().into(),
+        );
+        plist.insert("EnvironmentVariables".into(), env.into());
+        plist.insert("KeepAlive".into(), keep_alive.into());
+        plist.insert("RunAtLoad".into(), spec.run_at_load.into());
+        plist.insert("Nice".into(), spec.nice.unwrap_or(DEFAULT_NICE).into());
+        plist.insert("ProcessType".into(), "Interactive".into());
+        let log_path = |path: &Option<PathBuf>, default: &str| {
+            path.as_ref()
+                .map_or_else(|| default.to_owned(), |p| p.to_string_lossy().into_owned())
+        };
+        plist.insert(
+            "StandardOutPath".into(),
+            log_path(&spec.stdout_path, &self.raw.out_log_path).into(),
+        );
+        plist.insert(
+            "StandardErrorPath".into(),
+            log_path(&spec.stderr_path, &self.raw.error_log_path).into(),
+        );
+        if let Some(interval) = self.throttle_interval {
+            plist.insert("ThrottleInterval".into(), interval.into());
+        }
+        if let Some(session_type) = &self.session_type {
+            plist.insert("LimitLoadToSessionType".into(), session_type.clone().into());
+        }
+        plist.into()
+    }
+}
+
+impl ServiceManager for Launchd {
+    fn definition_path(&self) -> &Path {
+        Path::new(&self.raw.plist_path)
+    }
+
+    fn render(&self, spec: &ServiceSpec) -> String {
+        let mut buf = vec![];
+        (self.launchd_plist(spec).to_writer_xml(&mut buf))
+            .expect("serializing a plist to memory should never fail");
+        String::from_utf8(buf).expect("a plist in XML should always be valid UTF-8")
+    }
+
+    fn parse(&self, definition: &str) -> Option<ServiceSpec> {
+        let plist = Value::from_reader_xml(definition.as_bytes()).ok()?;
+        let plist = plist.as_dictionary()?;
+        let string = |key| plist.get(key).and_then(Value::as_string);
+        // Log paths and niceness are left out when they are the default ones.
+        let log_path = |key, default: &str| {
+            string(key)
+                .filter(|&path| path != default)
+                .map(PathBuf::from)
+        };
+        let mut spec = ServiceSpec {
+            bin_path: string("Program")?.into(),
+            stdout_path: log_path("StandardOutPath", &self.raw.out_log_path),
+            stderr_path: log_path("StandardErrorPath", &self.raw.error_log_path),
+            nice: (plist.get("Nice").and_then(Value::as_signed_integer))
+                .and_then(|nice| i32::try_from(nice).ok())
+                .filter(|&nice| nice != DEFAULT_NICE),
+            run_at_load: (plist.get("RunAtLoad").and_then(Value::as_boolean)).unwrap_or(false),
+            ..ServiceSpec::default()
+        };
+        for (key, val) in plist.get("EnvironmentVariables")?.as_dictionary()? {
+            spec.set_env_var(key.clone(), val.as_string()?.to_owned());
+        }
+        Some(spec)
+    }
+
+    fn is_loaded(&self) -> bool {
+        Command::new("/bin/launchctl")
+            .args(["print", &self.raw.service_target])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    }
+
+    fn start(&self) -> Result<()> {
+        Ok(self.raw.start()?)
+    }
+
+    fn stop(&self) -> Result<()> {
+        Ok(self.raw.stop()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plist_round_trip() {
+        let launchd = Launchd {
+            throttle_interval: Some(10),
+            ..Launchd::new(
+                launchctl::Service::builder()
+                    .name("io.github.rami3l.clavy")
+                    .uid("501")
+                    .build(),
+            )
+        };
+        let spec = ServiceSpec {
+            bin_path: "/Users/me/R&D <tools>/clavy".into(),
+            detect_popup: Some(vec!["com.raycast.*".into(), "/^a&b<c>$/".into()]),
+            config_path: Some("/Users/me/clavy.toml".into()),
+            env: [("RUST_LOG".into(), "debug".into())].into(),
+            stdout_path: Some("/Users/me/Library/Logs/clavy.log".into()),
+            nice: Some(5),
+            run_at_load: false,
+            ..ServiceSpec::default()
+        };
+        let rendered = launchd.render(&spec);
+        assert!(rendered.contains("R&amp;D &lt;tools&gt;"));
+
+        assert_eq!(launchd.parse(&rendered), Some(spec.clone()));
+
+        let parsed = Value::from_reader_xml(rendered.as_bytes()).unwrap();
+        assert_eq!(parsed, launchd.launchd_plist(&spec));
+        let plist = parsed.as_dictionary().unwrap();
+        let get = |key| plist.get(key).unwrap();
+        assert_eq!(get("Label").as_string(), Some("io.github.rami3l.clavy"));
+        assert_eq!(
+            get("Program").as_string(),
+            Some("/Users/me/R&D <tools>/clavy")
+        );
+        assert_eq!(get("Nice").as_signed_integer(), Some(5));
+        assert_eq!(get("RunAtLoad").as_boolean(), Some(false));
+        assert_eq!(
+            get("StandardOutPath").as_string(),
+            Some("/Users/me/Library/Logs/clavy.log")
+        );
+        assert_eq!(get("ThrottleInterval").as_unsigned_integer(), Some(10));
+        assert_eq!(get("LimitLoadToSessionType").as_string(), Some("Aqua"));
+        assert_eq!(
+            get("StandardErrorPath").as_string(),
+            Some("/tmp/io.github.rami3l.clavy_501.err.log")
+        );
+
+        let env = get("EnvironmentVariables").as_dictionary().unwrap();
+        let env = |key| env.get(key).and_then(Value::as_string);
+        assert_eq!(env("CLAVY_DETECT_POPUP"), Some("com.raycast.*,/^a&b<c>$/"));
+        assert_eq!(env("CLAVY_CONFIG"), Some("/Users/me/clavy.toml"));
+        assert_eq!(env("RUST_LOG"), Some("debug"));
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use super::{ServiceManager, ServiceSpec};
+use crate::{
+    error::{Error, Result},
+    util::config_home,
+};
+
+/// The name of the systemd unit.
+pub const UNIT_NAME: &str = "clavy.service";
+
+/// The [`ServiceManager`] running the service as a systemd user unit.
+#[derive(Clone, Debug)]
+pub struct Systemd {
+    unit_path: PathBuf,
+}
+
+impl Systemd {
+    /// Creates the user unit under `$XDG_CONFIG_HOME/systemd/user`.
+    pub fn try_new() -> Result<Self> {
+        Ok(Self::with_unit_path(
+            config_home()?.join("systemd/user").join(UNIT_NAME),
+        ))
+    }
+
+    #[must_use]
+    pub fn with_unit_path(unit_path: impl Into<PathBuf>) -> Self {
+        Self {
+            unit_path: unit_path.into(),
+        }
+    }
+
+    /// Runs `systemctl --user` with `args`, failing if it exits unsuccessfully.
+    fn systemctl(args: &[&str]) -> Result<()> {
+        let status = Command::new("systemctl")
+            .arg("--user")
+            .args(args)
+            .stdout(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(Error::CommandFailed(format!(
+                "systemctl --user {}",
+                args.join(" ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ServiceManager for Systemd {
+    fn definition_path(&self) -> &Path {
+        &self.unit_path
+    }
+
+    fn render(&self, spec: &ServiceSpec) -> String {
+        let mut unit = String::from(
+            "[Unit]\n\
+             Description=An input source switching daemon\n\
+             PartOf=graphical-session.target\n\
+             After=graphical-session.target\n\
+             \n\
+             [Service]\n\
+             Type=simple\n",
+        );
+        _ = writeln!(
+            unit,
+            "ExecStart={}",
+            quote(&spec.bin_path.to_string_lossy()).replace('$', "$$")
+        );
+        unit.push_str("Restart=on-failure\n");
+        if let Some(nice) = spec.nice {
+            _ = writeln!(unit, "Nice={nice}");
+        }
+        if let Some(path) = &spec.stdout_path {
+            _ = writeln!(unit, "StandardOutput=append:{}", escape_path(path));
+        }
+        if let Some(path) = &spec.stderr_path {
+            _ = writeln!(unit, "StandardError=append:{}", escape_path(path));
+        }
+        for (key, val) in spec.env_vars() {
+            _ = writeln!(unit, "Environment={}", quote(&format!("{key}={val}")));
+        }
+        // Without an `[Install]` section, enabling the unit does not make it
+        // start along with the graphical session.
+        if spec.run_at_load {
+            unit.push_str(
+                "\n\
+                 [Install]\n\
+                 WantedBy=graphical-session.target\n",
+            );
+        }
+        unit
+    }
+
+    fn parse(&self, definition: &str) -> Option<ServiceSpec> {
+        let mut spec = ServiceSpec {
+            run_at_load: false,
+            ..ServiceSpec::default()
+        };
+        let mut bin_path = None;
+        for line in definition.lines() {
+            if line == "[Install]" {
+                spec.run_at_load = true;
+                continue;
+            }
+            let Some((key, val)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "ExecStart" => bin_path = Some(unquote(&val.replace("$$", "$"))?),
+                "Nice" => spec.nice = Some(val.parse().ok()?),
+                "StandardOutput" => {
+                    spec.stdout_path = Some(unescape_path(val.strip_prefix("append:")?));
+                }
+                "StandardError" => {
+                    spec.stderr_path = Some(unescape_path(val.strip_prefix("append:")?));
+                }
+                "Environment" => {
+                    let var = unquote(val)?;
+                    let (key, val) = var.split_once('=')?;
+                    spec.set_env_var(key.into(), val.into());
+                }
+                _ => (),
+            }
+        }
+        spec.bin_path = bin_path?.into();
+        Some(spec)
+    }
+
+    fn is_loaded(&self) -> bool {
+        Self::systemctl(&["is-active", "--quiet", UNIT_NAME]).is_ok()
+    }
+
+    fn start(&self) -> Result<()> {
+        Self::systemctl(&["daemon-reload"])?;
+        Self::systemctl(&["enable", "--now", UNIT_NAME])
+    }
+
+    fn stop(&self) -> Result<()> {
+        Self::systemctl(&["disable", "--now", UNIT_NAME])
+    }
+}
+
+/// Escapes the specifiers introduced by `%` in `path`, which cannot be quoted
+/// in the settings redirecting the output of a unit.
+fn escape_path(path: &Path) -> String {
+    path.to_string_lossy().replace('%', "%%")
+}
+
+fn unescape_path(s: &str) -> PathBuf {
+    s.replace("%%", "%").into()
+}
+
+/// Quotes `s` as a single word of a systemd unit setting, escaping the
+/// specifiers introduced by `%` as well.
+fn quote(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '%' => res.push_str("%%"),
+            '\n' => res.push_str("\\n"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Reverts [`quote`], returning `None` if `s` is not quoted as such.
+fn unquote(s: &str) -> Option<String> {
+    let mut chars = s.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut res = String::new();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(c @ ('"' | '\\'))) => res.push(c),
+            ('\\', Some('n')) => res.push('\n'),
+            ('%', Some('%')) => res.push('%'),
+            ('"' | '\\' | '%', _) => return None,
+            _ => {
+                res.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_unit() {
+        let systemd = Systemd::with_unit_path("/home/me/.config/systemd/user/clavy.service");
+        let spec = ServiceSpec {
+            bin_path: "/home/me/my $bins/clavy".into(),
+            detect_popup: Some(vec!["org.kde.krunner".into(), "/^\"100%\"$/".into()]),
+            config_path: Some("/home/me/clavy.toml".into()),
+            env: [("RUST_LOG".into(), "debug".into())].into(),
+            stdout_path: Some("/home/me/logs/clavy 100%.log".into()),
+            stderr_path: None,
+            nice: Some(-5),
+            run_at_load: true,
+        };
+        assert_eq!(
+            systemd.render(&spec),
+            r#"[Unit]
+Description=An input source switching daemon
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=simple
+ExecStart="/home/me/my $$bins/clavy"
+Restart=on-failure
+Nice=-5
+StandardOutput=append:/home/me/logs/clavy 100%%.log
+Environment="NO_COLOR=1"
+Environment="CLAVY_DETECT_POPUP=org.kde.krunner,/^\"100%%\"$/"
+Environment="CLAVY_CONFIG=/home/me/clavy.toml"
+Environment="RUST_LOG=debug"
+
+[Install]
+WantedBy=graphical-session.target
+"#
+        );
+
+        assert_eq!(systemd.parse(&systemd.render(&spec)), Some(spec.clone()));
+
+        let spec = ServiceSpec {
+            detect_popup: None,
+            config_path: None,
+            run_at_load: false,
+            ..spec
+        };
+        let rendered = systemd.render(&spec);
+        assert!(!rendered.contains("CLAVY_DETECT_POPUP"));
+        assert!(!rendered.contains("CLAVY_CONFIG"));
+        assert!(!rendered.contains("[Install]"));
+        assert_eq!(systemd.parse(&rendered), Some(spec));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// The input sources remembered for each app, keyed by bundle ID.
+///
+/// The input sources can also be remembered for individual windows of an
+/// app, which are only kept in memory since window identities do not outlive
+/// the windows themselves.
+#[must_use]
+#[derive(Default, Clone, Debug)]
+pub struct InputSourceState(Arc<Mutex<StateInner>>);
+
+#[derive(Default, Debug)]
+struct StateInner {
+    apps: HashMap<String, String>,
+    windows: HashMap<(String, String), String>,
+}
+
+/// The on-disk representation of an [`InputSourceState`].
+#[derive(Debug, Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    #[serde(default)]
+    input_sources: BTreeMap<String, String>,
+}
+
+impl InputSourceState {
+    /// The version of the on-disk state format.
+    ///
+    /// This should be bumped whenever a breaking change is made to the format,
+    /// so that older versions of clavy refuse to load the state saved by newer
+    /// ones.
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, bundle_id: String, input_source: String) {
+        self.0.lock().unwrap().apps.insert(bundle_id, input_source);
+    }
+
+    pub fn load(&self, bundle_id: &str) -> Option<String> {
+        (self.0.lock().unwrap().apps)
+            .get(bundle_id)
+            .map(ToOwned::to_owned)
+    }
+
+    /// Forgets the input source remembered for `bundle_id` and its windows,
+    /// returning the one of the app itself if any.
+    #[allow(clippy::must_use_candidate)]
+    pub fn forget(&self, bundle_id: &str) -> Option<String> {
+        let mut inner = self.0.lock().unwrap();
+        inner.windows.retain(|(app, _), _| app != bundle_id);
+        inner.apps.remove(bundle_id)
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.apps.clear();
+        inner.windows.clear();
+    }
+
+    /// Remembers `input_source` for the window `window` of `bundle_id`.
+    pub fn save_window(&self, bundle_id: String, window: String, input_source: String) {
+        (self.0.lock().unwrap().windows).insert((bundle_id, window), input_source);
+    }
+
+    pub fn load_window(&self, bundle_id: &str, window: &str) -> Option<String> {
+        (self.0.lock().unwrap().windows)
+            .get(&(bundle_id.to_owned(), window.to_owned()))
+            .map(ToOwned::to_owned)
+    }
+
+    /// Forgets the input source remembered for the window `window` of
+    /// `bundle_id`, returning it if any.
+    #[allow(clippy::must_use_candidate)]
+    pub fn forget_window(&self, bundle_id: &str, window: &str) -> Option<String> {
+        (self.0.lock().unwrap().windows).remove(&(bundle_id.to_owned(), window.to_owned()))
+    }
+
+    /// Returns the number of windows with a remembered input source.
+    #[must_use]
+    pub fn window_count(&self) -> usize {
+        self.0.lock().unwrap().windows.len()
+    }
+
+    /// Returns a sorted copy of all the input sources remembered for apps.
+    #[must_use]
+    pub fn entries(&self) -> BTreeMap<String, String> {
+        let inner = self.0.lock().unwrap();
+        (inner.apps.iter())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Restores the state previously persisted to `path`.
+    ///
+    /// An empty state is returned if `path` does not exist yet.
+    pub fn restore(path: &Path) -> Result<Self> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: StateFile = serde_json::from_slice(&content)?;
+        if file.version > Self::SCHEMA_VERSION {
+            return Err(Error::UnsupportedStateVersion(file.version));
+        }
+        Ok(Self(Arc::new(Mutex::new(StateInner {
+            apps: file.input_sources.into_iter().collect(),
+            windows: HashMap::new(),
+        }))))
+    }
+
+    /// Persists the state to `path`, creating its parent directories if
+    /// necessary.
+    ///
+    /// The state is first written and synced to a temporary file next to
+    /// `path` before being renamed over it, so `path` is never left
+    /// half-written.
+    pub fn persist(&self, path: &Path) -> Result<()> {
+        let file = StateFile {
+            version: Self::SCHEMA_VERSION,
+            input_sources: self.entries(),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&file)?)?;
+        // Make sure the content has hit the disk before it replaces the old one,
+        // or a crash might leave an empty file behind.
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persist_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/state.json");
+        assert!(
+            InputSourceState::restore(&path)
+                .unwrap()
+                .entries()
+                .is_empty()
+        );
+
+        let state = InputSourceState::new();
+        state.save("foo".into(), "com.apple.keylayout.ABC".into());
+        state.save("bar".into(), "com.apple.inputmethod.Kotoeri".into());
+        state.persist(&path).unwrap();
+        state.save("foo".into(), "com.apple.inputmethod.SCIM.ITABC".into());
+        state.persist(&path).unwrap();
+
+        let restored = InputSourceState::restore(&path).unwrap();
+        assert_eq!(restored.entries(), state.entries());
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_window_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let state = InputSourceState::new();
+        state.save("foo".into(), "com.apple.keylayout.ABC".into());
+        state.save_window("foo".into(), "1".into(), "com.apple.keylayout.US".into());
+        state.save_window("foo".into(), "2".into(), "com.apple.keylayout.ABC".into());
+        assert_eq!(
+            state.load_window("foo", "1").as_deref(),
+            Some("com.apple.keylayout.US")
+        );
+        assert_eq!(state.load_window("bar", "1"), None);
+        assert_eq!(state.window_count(), 2);
+
+        // Windows are neither listed nor persisted.
+        assert_eq!(state.entries().len(), 1);
+        state.persist(&path).unwrap();
+        assert_eq!(InputSourceState::restore(&path).unwrap().window_count(), 0);
+
+        assert!(state.forget_window("foo", "2").is_some());
+        assert_eq!(state.window_count(), 1);
+        assert!(state.forget("foo").is_some());
+        assert_eq!(state.window_count(), 0);
+    }
+
+    #[test]
+    fn test_restore_future_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, r#"{"version": 999, "input_sources": {}}"#).unwrap();
+        assert!(matches!(
+            InputSourceState::restore(&path),
+            Err(Error::UnsupportedStateVersion(999))
+        ));
+    }
+}
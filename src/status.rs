@@ -0,0 +1,187 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, util::is_process_alive};
+
+/// The information about a running daemon, written to disk upon startup so
+/// that other clavy processes can find it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    pub pid: u32,
+    /// The startup time of the daemon, in seconds since the Unix epoch.
+    pub started_at: u64,
+}
+
+impl DaemonInfo {
+    /// Returns the information about the current process.
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            pid: process::id(),
+            started_at: unix_time(SystemTime::now()),
+        }
+    }
+
+    /// Reads the daemon information from `path`, returning `None` if
+    /// `path` does not exist.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Returns if the process identified by this information is still
+    /// running.
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        libc::pid_t::try_from(self.pid).is_ok_and(is_process_alive)
+    }
+
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        Duration::from_secs(unix_time(SystemTime::now()).saturating_sub(self.started_at))
+    }
+}
+
+fn unix_time(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// A snapshot of the status of clavy as reported by `clavy status`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Status {
+    /// The path of the service definition, e.g. the launch agent.
+    pub service_path: PathBuf,
+    /// Whether the service definition has been installed.
+    pub installed: bool,
+    /// Whether the service has been loaded by the service manager.
+    pub loaded: bool,
+    /// The running daemon, if any.
+    pub daemon: Option<DaemonStatus>,
+    /// The app currently in focus.
+    pub current_app: Option<String>,
+    /// The current input source.
+    pub input_source: Option<String>,
+    /// The number of apps with a remembered input source.
+    pub mappings: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub uptime_secs: u64,
+}
+
+impl From<DaemonInfo> for DaemonStatus {
+    fn from(info: DaemonInfo) -> Self {
+        Self {
+            pid: info.pid,
+            uptime_secs: info.uptime().as_secs(),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        let or_unknown = |s: &Option<String>| s.clone().unwrap_or_else(|| "<unknown>".into());
+
+        writeln!(f, "service:      {}", self.service_path.display())?;
+        writeln!(f, "installed:    {}", yes_no(self.installed))?;
+        writeln!(f, "loaded:       {}", yes_no(self.loaded))?;
+        match self.daemon {
+            Some(DaemonStatus { pid, uptime_secs }) => writeln!(
+                f,
+                "daemon:       running (PID {pid}, up {})",
+                HumanDuration(uptime_secs)
+            )?,
+            None => writeln!(f, "daemon:       not running")?,
+        }
+        writeln!(f, "current app:  {}", or_unknown(&self.current_app))?;
+        writeln!(f, "input source: {}", or_unknown(&self.input_source))?;
+        write!(f, "mappings:     {}", self.mappings)
+    }
+}
+
+/// A duration in seconds, displayed as e.g. `1d 2h 3m 4s`.
+struct HumanDuration(u64);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0;
+        let mut started = false;
+        for (n, unit) in [
+            (secs / 86400, "d"),
+            (secs / 3600 % 24, "h"),
+            (secs / 60 % 60, "m"),
+        ] {
+            if started || n != 0 {
+                write!(f, "{n}{unit} ")?;
+                started = true;
+            }
+        }
+        write!(f, "{}s", secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_duration() {
+        assert_eq!(HumanDuration(0).to_string(), "0s");
+        assert_eq!(HumanDuration(61).to_string(), "1m 1s");
+        assert_eq!(HumanDuration(86400 + 5).to_string(), "1d 0h 0m 5s");
+    }
+
+    #[test]
+    fn test_daemon_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.json");
+        assert_eq!(DaemonInfo::read(&path).unwrap(), None);
+
+        let info = DaemonInfo::current();
+        info.write(&path).unwrap();
+        let read = DaemonInfo::read(&path).unwrap().unwrap();
+        assert_eq!(read, info);
+        assert!(read.is_alive());
+        assert!(read.uptime() < Duration::from_mins(1));
+    }
+
+    #[test]
+    fn test_status_json() {
+        let status = Status {
+            service_path: "/tmp/clavy.plist".into(),
+            installed: true,
+            loaded: true,
+            daemon: Some(DaemonStatus {
+                pid: 42,
+                uptime_secs: 3,
+            }),
+            current_app: Some("com.apple.Safari".into()),
+            input_source: None,
+            mappings: 2,
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["daemon"]["pid"], 42);
+        assert_eq!(json["input_source"], serde_json::Value::Null);
+        assert!(status.to_string().contains("running (PID 42, up 3s)"));
+    }
+}
@@ -0,0 +1,39 @@
+use std::{any::Any, fmt};
+
+use smol::channel::Receiver;
+
+/// A stream of values produced by an event source.
+///
+/// # Note
+/// The subscription is cancelled once this value is dropped, even if clones
+/// of its [`Receiver`] are still alive.
+#[must_use]
+pub struct Subscription<T> {
+    rx: Receiver<T>,
+    _guard: Box<dyn Any>,
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("rx", &self.rx)
+            .field("_guard", &"<guard>")
+            .finish()
+    }
+}
+
+impl<T> Subscription<T> {
+    /// Creates a new subscription from a channel receiver, keeping `guard`
+    /// alive for as long as the subscription itself.
+    pub fn new(rx: Receiver<T>, guard: impl Any) -> Self {
+        Self {
+            rx,
+            _guard: Box::new(guard),
+        }
+    }
+
+    #[must_use]
+    pub fn receiver(&self) -> Receiver<T> {
+        self.rx.clone()
+    }
+}
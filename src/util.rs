@@ -1,29 +1,43 @@
+use std::{env, io, path::PathBuf};
+#[cfg(target_os = "macos")]
 use std::{
     ffi::{CStr, OsStr, c_int},
     os::unix::ffi::OsStrExt,
-    path::PathBuf,
     ptr,
 };
 
+#[cfg(target_os = "macos")]
 use accessibility_sys::{
-    AXIsProcessTrustedWithOptions, AXUIElementCopyAttributeValue, AXUIElementCreateSystemWide,
-    AXUIElementGetPid, AXUIElementRef, kAXFocusedApplicationAttribute, kAXTrustedCheckOptionPrompt,
+    AXError, AXIsProcessTrustedWithOptions, AXUIElementCopyAttributeValue,
+    AXUIElementCreateApplication, AXUIElementCreateSystemWide, AXUIElementGetPid, AXUIElementRef,
+    kAXFocusedApplicationAttribute, kAXFocusedWindowAttribute, kAXTitleAttribute,
+    kAXTrustedCheckOptionPrompt,
 };
+#[cfg(target_os = "macos")]
 use core_foundation::{
-    base::{CFTypeRef, FromVoid, TCFType},
+    base::{CFRelease, CFType, CFTypeRef, FromVoid, TCFType},
     boolean::CFBoolean,
     string::CFString,
 };
+#[cfg(target_os = "macos")]
 use core_graphics::display::CFDictionary;
+#[cfg(target_os = "macos")]
 use libc::pid_t;
+#[cfg(target_os = "macos")]
 use objc2::rc::Retained;
+#[cfg(target_os = "macos")]
 use objc2_app_kit::{NSRunningApplication, NSWorkspace, NSWorkspaceApplicationKey};
+#[cfg(target_os = "macos")]
 use objc2_foundation::{NSNotification, NSString};
+#[cfg(target_os = "macos")]
 use tracing::debug;
 
+#[cfg(target_os = "macos")]
 use crate::error::AccessibilityError;
+use crate::error::{Error, Result};
 
 /// Returns the path of the current executable.
+#[cfg(target_os = "macos")]
 #[must_use]
 pub fn exe_path() -> Option<PathBuf> {
     #[link(name = "Foundation", kind = "framework")]
@@ -41,6 +55,79 @@ pub fn exe_path() -> Option<PathBuf> {
     Some(OsStr::from_bytes(path.to_bytes()).into())
 }
 
+/// Returns the path of the current executable.
+#[cfg(not(target_os = "macos"))]
+#[must_use]
+pub fn exe_path() -> Option<PathBuf> {
+    env::current_exe().ok()
+}
+
+/// Returns if the process identified by `pid` is still running.
+#[must_use]
+pub fn is_process_alive(pid: libc::pid_t) -> bool {
+    // Sending the signal `0` only checks if the process exists.
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Returns the directory where clavy keeps its persistent data.
+///
+/// This is `~/Library/Application Support/clavy` on macOS, and
+/// `$XDG_DATA_HOME/clavy` (defaulting to `~/.local/share/clavy`) elsewhere.
+pub fn data_dir() -> Result<PathBuf> {
+    let home = env::home_dir().ok_or(Error::HomeNotSet);
+    let base = if cfg!(target_os = "macos") {
+        home?.join("Library/Application Support")
+    } else {
+        match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => home?.join(".local/share"),
+        }
+    };
+    Ok(base.join("clavy"))
+}
+
+/// Returns the base directory of user-specific configuration files.
+///
+/// This is `$XDG_CONFIG_HOME`, defaulting to `~/.config`.
+pub fn config_home() -> Result<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(env::home_dir().ok_or(Error::HomeNotSet)?.join(".config")),
+    }
+}
+
+/// Returns the default path of the configuration file.
+///
+/// This is `$XDG_CONFIG_HOME/clavy/config.toml`, defaulting to
+/// `~/.config/clavy/config.toml`.
+pub fn config_path() -> Result<PathBuf> {
+    Ok(config_home()?.join("clavy/config.toml"))
+}
+
+/// Returns the path of the file where the input source state is persisted.
+pub fn state_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("state.json"))
+}
+
+/// Returns the path of the control socket of the daemon.
+///
+/// This is `$XDG_RUNTIME_DIR/clavy/clavy.sock` if `$XDG_RUNTIME_DIR` is set,
+/// and `clavy.sock` under [`data_dir`] otherwise.
+pub fn socket_path() -> Result<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("clavy"),
+        _ => data_dir()?,
+    };
+    Ok(dir.join("clavy.sock"))
+}
+
+/// Returns the path of the file where the running daemon advertises itself.
+pub fn daemon_info_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("daemon.json"))
+}
+
+#[cfg(target_os = "macos")]
 /// Returns if the right privileges have been granted to use the
 /// Accessibility APIs.
 // https://github.com/koekeishiya/yabai/blob/a8eb6b1a7da4e33954b716b424eb51ce47317865/src/misc/helpers.h#L328
@@ -55,7 +142,13 @@ pub fn has_ax_privileges() -> bool {
     }
 }
 
-fn ax_ui_element_value(elem: AXUIElementRef, key: &str) -> Result<CFTypeRef, AccessibilityError> {
+#[cfg(target_os = "macos")]
+/// Returns the value of the attribute `key` of `elem`, to be released by the
+/// caller.
+pub fn ax_ui_element_value(
+    elem: AXUIElementRef,
+    key: &str,
+) -> Result<CFTypeRef, AccessibilityError> {
     let mut val: CFTypeRef = ptr::null_mut();
     AccessibilityError::wrap(unsafe {
         AXUIElementCopyAttributeValue(elem, CFString::new(key).as_concrete_TypeRef(), &raw mut val)
@@ -63,6 +156,7 @@ fn ax_ui_element_value(elem: AXUIElementRef, key: &str) -> Result<CFTypeRef, Acc
     Ok(val)
 }
 
+#[cfg(target_os = "macos")]
 /// Converts a running application's PID to its Bundle ID.
 #[must_use]
 pub fn bundle_id_from_pid(pid: pid_t) -> Option<Retained<NSString>> {
@@ -72,20 +166,31 @@ pub fn bundle_id_from_pid(pid: pid_t) -> Option<Retained<NSString>> {
         .find_map(|app| (app.processIdentifier() == pid).then(|| app.bundleIdentifier())?)
 }
 
-/// Returns the PID of the frontmost application from a notification
-/// sent by `NotificationCenter`.
+#[cfg(target_os = "macos")]
+/// Returns the application that a notification sent by `NotificationCenter`
+/// is about.
 ///
 /// # Note
 /// This function could always return `None` for certain notification types.
-pub fn bundle_id_from_notification(notif: &NSNotification) -> Option<Retained<NSString>> {
+pub fn app_from_notification(notif: &NSNotification) -> Option<Retained<NSRunningApplication>> {
     unsafe {
-        Retained::cast_unchecked::<NSRunningApplication>(
+        Some(Retained::cast_unchecked::<NSRunningApplication>(
             notif.userInfo()?.objectForKey(NSWorkspaceApplicationKey)?,
-        )
-        .bundleIdentifier()
+        ))
     }
 }
 
+#[cfg(target_os = "macos")]
+/// Returns the PID of the frontmost application from a notification
+/// sent by `NotificationCenter`.
+///
+/// # Note
+/// This function could always return `None` for certain notification types.
+pub fn bundle_id_from_notification(notif: &NSNotification) -> Option<Retained<NSString>> {
+    app_from_notification(notif)?.bundleIdentifier()
+}
+
+#[cfg(target_os = "macos")]
 /// Returns the PID of the frontmost application from the Accessibility APIs.
 pub fn pid_from_current_app() -> Result<pid_t, AccessibilityError> {
     unsafe {
@@ -100,6 +205,67 @@ pub fn pid_from_current_app() -> Result<pid_t, AccessibilityError> {
     }
 }
 
+#[cfg(target_os = "macos")]
+/// Calls `f` with the focused window of the application with the given PID
+/// from the Accessibility APIs.
+fn with_focused_window<T>(pid: pid_t, f: impl FnOnce(AXUIElementRef) -> Option<T>) -> Option<T> {
+    unsafe {
+        let app = AXUIElementCreateApplication(pid);
+        let window = ax_ui_element_value(app, kAXFocusedWindowAttribute);
+        CFRelease(app.cast());
+        let window = window
+            .inspect_err(|e| debug!("failed to get focused window of PID {pid}: {e:?}"))
+            .ok()? as AXUIElementRef;
+        let res = f(window);
+        CFRelease(window.cast());
+        res
+    }
+}
+
+#[cfg(target_os = "macos")]
+/// Returns the title of the focused window of the application with the given
+/// PID from the Accessibility APIs.
+#[must_use]
+pub fn focused_window_title(pid: pid_t) -> Option<String> {
+    with_focused_window(pid, |window| unsafe {
+        let title = ax_ui_element_value(window, kAXTitleAttribute);
+        let title = title.ok().filter(|title| !title.is_null())?;
+        CFType::wrap_under_create_rule(title)
+            .downcast_into::<CFString>()
+            .map(|title| title.to_string())
+    })
+}
+
+#[cfg(target_os = "macos")]
+/// Returns the window number of the focused window of the application with
+/// the given PID from the Accessibility APIs.
+#[must_use]
+pub fn focused_window_number(pid: pid_t) -> Option<u32> {
+    with_focused_window(pid, |window| {
+        window_number(window)
+            .inspect_err(|e| debug!("failed to get window number of PID {pid}: {e:?}"))
+            .ok()
+    })
+}
+
+#[cfg(target_os = "macos")]
+/// Returns the window number of a window from the Accessibility APIs, the same
+/// as the one reported by `CGWindowListCopyWindowInfo`.
+pub fn window_number(window: AXUIElementRef) -> Result<u32, AccessibilityError> {
+    // This is private, but there is no public way of identifying a window.
+    // See: <https://github.com/koekeishiya/yabai/blob/a8eb6b1a7da4e33954b716b424eb51ce47317865/src/misc/extern.h>
+    #[link(name = "ApplicationServices", kind = "framework")]
+    unsafe extern "C" {
+        fn _AXUIElementGetWindow(elem: AXUIElementRef, number: *mut u32) -> AXError;
+    }
+
+    let mut number = 0;
+    #[allow(clippy::used_underscore_items)]
+    AccessibilityError::wrap(unsafe { _AXUIElementGetWindow(window, &raw mut number) })?;
+    Ok(number)
+}
+
+#[cfg(target_os = "macos")]
 /// Returns the Bundle ID of the frontmost application as indicated by
 /// `NSWorkspace`.
 ///
@@ -113,6 +279,7 @@ pub fn bundle_id_from_frontmost_app() -> Option<Retained<NSString>> {
         .bundleIdentifier()
 }
 
+#[cfg(target_os = "macos")]
 /// Returns the Bundle ID of the currently focused application.
 ///
 /// # Note